@@ -1,70 +1,359 @@
-use axum::{Json, extract::{FromRequestParts}, http::request::Parts, response::IntoResponse};
+use axum::{Json, extract::{FromRequestParts}, http::{HeaderMap, HeaderValue, Method, header, request::Parts}, response::IntoResponse};
 use hyper::{StatusCode};
 use serde::{Deserialize, Serialize};
 use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey};
 use chrono::{Utc, Duration};
 use serde_json::json;
 
-use crate::{env, json::JSON, telegram};
+use crate::{env, json::JSON, telegram::{self, InitDataPolicy, ReplayStore}};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: i64,      // The user_id
     pub exp: usize,    // Expiration time (Unix timestamp)
     pub iat: usize,    // Issued at (Unix timestamp)
+
+    // Which bot signed the login, tokens issued before multi-bot support have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bot: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bot_id: Option<i64>,
+}
+
+// One of the bots (staging, production, regional...) fronting the same backend
+#[derive(Debug, Clone)]
+pub struct BotCredentials {
+    pub name: String,
+    pub token: String,
+}
+
+impl BotCredentials {
+    pub fn new(name: impl Into<String>, token: impl Into<String>) -> Self {
+        BotCredentials { name: name.into(), token: token.into() }
+    }
+
+    // Bot tokens look like "<bot_id>:<secret>"
+    pub fn id(&self) -> Option<i64> {
+        self.token.split(':').next()?.parse().ok()
+    }
+}
+
+// Reads bots from a variable formatted as "name=token,name=token"
+pub fn bots_from_env(var: &str) -> Vec<BotCredentials> {
+    env::get(var)
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|pair| {
+            let (name, token) = pair.trim().split_once('=')?;
+            Some(BotCredentials::new(name.trim(), token.trim()))
+        })
+        .collect()
+}
+
+pub const TOKEN_TTL_DAYS: i64 = 60;
+
+pub const SESSION_COOKIE: &str = "session";
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
 }
 
+impl SameSite {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+// Settings for delivering the token as an HttpOnly session cookie
+#[derive(Debug, Clone)]
+pub struct CookieConfig {
+    pub same_site: SameSite,
+    pub domain: Option<String>,
+    pub path: String,
+    pub max_age_secs: i64,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        CookieConfig {
+            same_site: SameSite::Lax,
+            domain: None,
+            path: "/".to_string(),
+            max_age_secs: TOKEN_TTL_DAYS * 24 * 60 * 60,
+        }
+    }
+}
+
+impl CookieConfig {
+    fn cookie(&self, name: &str, value: &str, http_only: bool, max_age_secs: i64) -> String {
+        let mut cookie = format!(
+            "{}={}; Path={}; Max-Age={}; Secure; SameSite={}",
+            name, value, self.path, max_age_secs, self.same_site.as_str()
+        );
+        if let Some(domain) = &self.domain {
+            cookie.push_str(&format!("; Domain={}", domain));
+        }
+        if http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        cookie
+    }
+}
+
+// Non-exhaustive so new fields don't break callers, build one with `LoginResult::new`
 #[derive(Debug, Serialize)]
+#[non_exhaustive]
 pub struct LoginResult {
     pub token: String,
     pub user: Option<telegram::User>,
     pub data: Option<JSON>,
-    pub is_created: bool
+    pub is_created: bool,
+
+    #[serde(skip)]
+    pub cookie: Option<CookieConfig>,
+    // Validated launch data (chat context, start_param...) of Mini App logins
+    #[serde(skip)]
+    pub init_data: Option<telegram::InitData>,
+}
+
+impl LoginResult {
+    pub fn new(token: String, user: Option<telegram::User>, data: Option<JSON>, is_created: bool) -> Self {
+        LoginResult {
+            token,
+            user,
+            data,
+            is_created,
+            cookie: None,
+            init_data: None,
+        }
+    }
+
+    // Also deliver the token as a session cookie (plus a CSRF cookie for double-submit)
+    pub fn with_cookie(mut self, config: CookieConfig) -> Self {
+        self.cookie = Some(config);
+        self
+    }
 }
 
 impl IntoResponse for LoginResult {
     fn into_response(self) -> axum::response::Response {
+        let Some(config) = self.cookie.filter(|_| !self.token.is_empty()) else {
+            let body = Json(json!({
+                "token": self.token,
+                "user": self.user,
+                "data": self.data,
+                "is_created": self.is_created
+            }));
+            return (StatusCode::OK, body).into_response();
+        };
+
+        // The CSRF token is readable by JS, which echoes it back in the CSRF_HEADER
+        let csrf_token = crate::uuid::new().simple().to_string();
+
         let body = Json(json!({
-            "token": self.token,
             "user": self.user,
             "data": self.data,
-            "is_created": self.is_created
+            "is_created": self.is_created,
+            "csrf_token": csrf_token,
         }));
-        (StatusCode::OK, body).into_response()
+
+        let mut response = (StatusCode::OK, body).into_response();
+        let cookies = [
+            config.cookie(SESSION_COOKIE, &self.token, true, config.max_age_secs),
+            config.cookie(CSRF_COOKIE, &csrf_token, false, config.max_age_secs),
+        ];
+        for cookie in cookies {
+            if let Ok(value) = HeaderValue::from_str(&cookie) {
+                response.headers_mut().append(header::SET_COOKIE, value);
+            }
+        }
+        response
+    }
+}
+
+// Clears the session and CSRF cookies
+#[derive(Default)]
+pub struct Logout(pub CookieConfig);
+
+impl IntoResponse for Logout {
+    fn into_response(self) -> axum::response::Response {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let cookies = [
+            self.0.cookie(SESSION_COOKIE, "", true, 0),
+            self.0.cookie(CSRF_COOKIE, "", false, 0),
+        ];
+        for cookie in cookies {
+            if let Ok(value) = HeaderValue::from_str(&cookie) {
+                response.headers_mut().append(header::SET_COOKIE, value);
+            }
+        }
+        response
     }
 }
 
+pub fn logout() -> Logout {
+    Logout::default()
+}
+
+pub fn get_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
 pub fn login(
     // Query(params): Query<HashMap<String, String>>,
     init_data: &str,
     bot_token: &str,
 ) -> LoginResult {
-
-    let valid = telegram::validate_init_data(init_data, &bot_token).unwrap_or(false);
-    if !valid {
+    let Ok(Some(init_data)) = telegram::validate_and_parse_init_data(init_data, bot_token) else {
         // println!("Invalid init_data");
-        return LoginResult {
-            user: None,
-            token: String::new(),
-            data: None,
-            is_created: false
-        };
+        return rejected();
+    };
+
+    let Some(user) = init_data.user.clone() else {
+        // println!("No user extracted from init data: {:?}", init_data);
+        return rejected();
+    };
+
+    LoginResult {
+        init_data: Some(init_data),
+        ..sign_in(user, None)
     }
+}
 
-    let user = telegram::extract_user(init_data);
+// Same as `login`, with a custom initData age policy and an optional replay
+// store so a launch payload can be exchanged for a token only once.
+pub async fn login_with(
+    init_data: &str,
+    bot_token: &str,
+    policy: &InitDataPolicy,
+    replay: Option<&dyn ReplayStore>,
+) -> LoginResult {
+    // Claimed only once the payload is known to be usable
+    let init_data = telegram::validate_and_parse_init_data_policy(init_data, bot_token, policy);
+    let Ok(Some(init_data)) = init_data else {
+        return rejected();
+    };
 
-    if user.is_none() {
-        // println!("No user extracted from init data: {:?}", init_data);
+    let Some(user) = init_data.user.clone() else {
+        return rejected();
+    };
 
-        return LoginResult {
-            user: None,
-            token: String::new(),
-            data: None,
-            is_created: false
-        };
+    if !matches!(telegram::claim_init_data(&init_data, policy, replay).await, Ok(true)) {
+        return rejected();
+    }
+
+    LoginResult {
+        init_data: Some(init_data),
+        ..sign_in(user, None)
+    }
+}
+
+// Same as `login`, but accepts initData signed by any of `bots`,
+// the matching bot is embedded in the token claims.
+pub fn login_multi(
+    init_data: &str,
+    bots: &[BotCredentials],
+) -> LoginResult {
+    let Some((bot, init_data)) = validate_multi(init_data, bots, &InitDataPolicy::default()) else {
+        return rejected();
+    };
+
+    let Some(user) = init_data.user.clone() else {
+        return rejected();
+    };
+
+    LoginResult {
+        init_data: Some(init_data),
+        ..sign_in(user, Some(bot))
+    }
+}
+
+pub async fn login_multi_with(
+    init_data: &str,
+    bots: &[BotCredentials],
+    policy: &InitDataPolicy,
+    replay: Option<&dyn ReplayStore>,
+) -> LoginResult {
+    let Some((bot, init_data)) = validate_multi(init_data, bots, policy) else {
+        return rejected();
+    };
+
+    let Some(user) = init_data.user.clone() else {
+        return rejected();
+    };
+
+    if !matches!(telegram::claim_init_data(&init_data, policy, replay).await, Ok(true)) {
+        return rejected();
+    }
+
+    LoginResult {
+        init_data: Some(init_data),
+        ..sign_in(user, Some(bot))
+    }
+}
+
+// The bot whose token matches the hash, only that one gets past the HMAC check
+fn validate_multi<'a>(
+    init_data: &str,
+    bots: &'a [BotCredentials],
+    policy: &InitDataPolicy,
+) -> Option<(&'a BotCredentials, telegram::InitData)> {
+    bots.iter().find_map(|bot| {
+        telegram::validate_and_parse_init_data_policy(init_data, &bot.token, policy)
+            .ok()
+            .flatten()
+            .map(|init_data| (bot, init_data))
+    })
+}
+
+// Login from the Telegram Login Widget (web dashboard outside of Telegram)
+pub fn login_widget(
+    data: &telegram::LoginWidgetData,
+    bot_token: &str,
+) -> LoginResult {
+    let valid = telegram::validate_login_widget(data, bot_token).unwrap_or(false);
+    if !valid {
+        return rejected();
+    }
+
+    sign_in(data.user(), None)
+}
+
+pub async fn login_widget_with(
+    data: &telegram::LoginWidgetData,
+    bot_token: &str,
+    policy: &InitDataPolicy,
+    replay: Option<&dyn ReplayStore>,
+) -> LoginResult {
+    let valid = telegram::validate_login_widget_with(data, bot_token, policy, replay).await.unwrap_or(false);
+    if !valid {
+        return rejected();
     }
-    let user = user.unwrap();
 
+    sign_in(data.user(), None)
+}
+
+fn rejected() -> LoginResult {
+    LoginResult::new(String::new(), None, None, false)
+}
+
+fn sign_in(user: telegram::User, bot: Option<&BotCredentials>) -> LoginResult {
     // let pool = state.pool.clone();
     // let data = extract_data(&user, pool).await;
     // if data.is_none() {
@@ -93,7 +382,7 @@ pub fn login(
 
     // 1. Create the claims
     let expiration = Utc::now()
-        .checked_add_signed(Duration::days(60))
+        .checked_add_signed(Duration::days(TOKEN_TTL_DAYS))
         .expect("valid timestamp")
         .timestamp();
 
@@ -101,6 +390,8 @@ pub fn login(
         sub: user_id,
         iat: Utc::now().timestamp() as usize,
         exp: expiration as usize,
+        bot: bot.map(|bot| bot.name.clone()),
+        bot_id: bot.and_then(|bot| bot.id()),
     };
 
     // 2. Sign the token
@@ -118,12 +409,16 @@ pub fn login(
         token: token,
         user: Some(user),
         data: None,
-        is_created: true
+        is_created: true,
+        cookie: None,
+        init_data: None,
     }
 }
 
 pub struct AuthenticatedUser {
     pub id: i64, // Using i64 assuming your Telegram/DB IDs are integers
+    pub bot: Option<String>, // Which app the user came from (see `login_multi`)
+    pub bot_id: Option<i64>,
 }
 
 // impl<S> FromRequestParts<S> for AuthenticatedUser
@@ -154,6 +449,48 @@ pub struct AuthenticatedUser {
 //     }
 // }
 
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+}
+
+// Token from the Authorization header or the session cookie, without CSRF checks
+pub fn request_token(headers: &HeaderMap) -> Option<&str> {
+    bearer_token(headers).or_else(|| get_cookie(headers, SESSION_COOKIE))
+}
+
+pub fn decode_token(token: &str) -> Option<Claims> {
+    let JWT_SECRET = env::get("JWT_SECRET")
+        .expect("JWT_SECRET IS NOT SETUP").into_bytes();
+
+    decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(&JWT_SECRET),
+        &Validation::default(),
+    )
+    .ok()
+    .map(|data| data.claims)
+}
+
+fn is_safe_method(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+fn csrf_matches(headers: &HeaderMap) -> bool {
+    let cookie = get_cookie(headers, CSRF_COOKIE);
+    let header = headers.get(CSRF_HEADER).and_then(|h| h.to_str().ok());
+
+    match (cookie, header) {
+        (Some(cookie), Some(header)) if !cookie.is_empty() && cookie.len() == header.len() => {
+            // constant-time comparison
+            cookie.bytes().zip(header.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+        }
+        _ => false,
+    }
+}
+
 impl<S> FromRequestParts<S> for AuthenticatedUser
 where
     S: Send + Sync,
@@ -161,26 +498,30 @@ where
     type Rejection = (StatusCode, &'static str);
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        // 1. Get the Bearer token
-        let auth_header = parts.headers
-            .get(axum::http::header::AUTHORIZATION)
-            .and_then(|h| h.to_str().ok())
-            .and_then(|h| h.strip_prefix("Bearer "))
-            .ok_or((StatusCode::UNAUTHORIZED, "Missing or invalid token"))?;
+        // 1. Get the Bearer token, falling back to the session cookie
+        let auth_header = match bearer_token(&parts.headers) {
+            Some(token) => token,
+            None => {
+                let token = get_cookie(&parts.headers, SESSION_COOKIE)
+                    .ok_or((StatusCode::UNAUTHORIZED, "Missing or invalid token"))?;
 
-        let JWT_SECRET = env::get("JWT_SECRET")
-            .expect("JWT_SECRET IS NOT SETUP").into_bytes();
+                // Cookies are sent automatically, so mutating requests must prove
+                // they can read the CSRF cookie (double-submit)
+                if !is_safe_method(&parts.method) && !csrf_matches(&parts.headers) {
+                    return Err((StatusCode::FORBIDDEN, "Missing or invalid CSRF token"));
+                }
+                token
+            }
+        };
 
         // 2. Decode and Validate the JWT
-        let token_data = decode::<Claims>(
-            auth_header,
-            &DecodingKey::from_secret(&JWT_SECRET),
-            &Validation::default(),
-        )
-        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid or expired token"))?;
+        let claims = decode_token(auth_header)
+            .ok_or((StatusCode::UNAUTHORIZED, "Invalid or expired token"))?;
 
         Ok(AuthenticatedUser {
-            id: token_data.claims.sub,
+            id: claims.sub,
+            bot: claims.bot,
+            bot_id: claims.bot_id,
         })
     }
 }
\ No newline at end of file
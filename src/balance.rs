@@ -0,0 +1,244 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+use crate::env;
+
+pub const DEFAULT_BALANCE_URL: &str = "https://prestige.up.railway.app";
+pub const INTERNAL_SECRET_HEADER: &str = "X-Internal-Secret";
+
+// Client of the balance service behind `currency` and `energy`, so staging and
+// tests can point it somewhere else than production
+#[derive(Clone)]
+pub struct BalanceClient {
+    base_url: String,
+    internal_secret: String,
+    project: Option<String>,
+    client: reqwest::Client,
+    retry: RetryPolicy,
+}
+
+impl std::fmt::Debug for BalanceClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BalanceClient")
+            .field("base_url", &self.base_url)
+            .field("project", &self.project)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BalanceClient {
+    pub fn new(base_url: impl Into<String>, internal_secret: impl Into<String>, client: reqwest::Client) -> Self {
+        BalanceClient {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            internal_secret: internal_secret.into(),
+            project: None,
+            client,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    // INTERNAL_SECRET is required, BALANCE_SERVICE_URL defaults to production
+    // and PROJECT_NAME is used by the energy endpoints
+    pub fn from_env(client: reqwest::Client) -> Result<Self, std::env::VarError> {
+        let base_url = env::get("BALANCE_SERVICE_URL").unwrap_or_else(|_| DEFAULT_BALANCE_URL.to_string());
+        let balance = Self::new(base_url, env::get("INTERNAL_SECRET")?, client);
+
+        Ok(match env::get("PROJECT_NAME") {
+            Ok(project) => balance.project(project),
+            Err(_) => balance,
+        })
+    }
+
+    // BALANCE_SERVICE_URL or production, for callers that only have a client and secret
+    pub fn with_secret(client: &reqwest::Client, internal_secret: &str) -> Self {
+        let base_url = env::get("BALANCE_SERVICE_URL").unwrap_or_else(|_| DEFAULT_BALANCE_URL.to_string());
+        Self::new(base_url, internal_secret, client.clone())
+    }
+
+    pub fn project(mut self, project: impl Into<String>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn project_name(&self) -> Option<&str> {
+        self.project.as_deref()
+    }
+
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path.trim_start_matches('/'))
+    }
+
+    // POST to the service, authenticated with the internal secret
+    pub fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .post(self.url(path))
+            .header(INTERNAL_SECRET_HEADER, &self.internal_secret)
+    }
+}
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+// Set by the service when it answers with the stored result of an earlier request
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+// Result of a mutating call the service answered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BalanceOutcome {
+    Applied { key: String, body: String },
+    // Already applied earlier under the same key, nothing changed now
+    Duplicate { key: String, body: String },
+    // Refused (insufficient funds, unknown user...), retrying won't help
+    Rejected { key: String, status: StatusCode, reason: String },
+}
+
+impl BalanceOutcome {
+    // Applied now or before
+    pub fn is_applied(&self) -> bool {
+        !matches!(self, BalanceOutcome::Rejected { .. })
+    }
+
+    pub fn key(&self) -> &str {
+        match self {
+            BalanceOutcome::Applied { key, .. }
+            | BalanceOutcome::Duplicate { key, .. }
+            | BalanceOutcome::Rejected { key, .. } => key,
+        }
+    }
+}
+
+// The service couldn't be reached or kept failing after all retries. The change
+// may or may not have been applied, retry later with the same key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BalanceError {
+    Unreachable { key: String, message: String },
+    Server { key: String, status: StatusCode, body: String },
+    // A local backend (e.g. the ledger) failed to read or write its storage
+    Storage { key: String, message: String },
+}
+
+impl BalanceError {
+    pub fn key(&self) -> &str {
+        match self {
+            BalanceError::Unreachable { key, .. }
+            | BalanceError::Server { key, .. }
+            | BalanceError::Storage { key, .. } => key,
+        }
+    }
+}
+
+impl std::fmt::Display for BalanceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BalanceError::Unreachable { message, .. } => write!(f, "Balance service unreachable: {}", message),
+            BalanceError::Server { status, body, .. } => write!(f, "Balance service error ({}): {}", status, body),
+            BalanceError::Storage { message, .. } => write!(f, "Balance storage error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for BalanceError {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+    // Longest Retry-After of a 429 worth waiting for, longer ones fail right away
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            min_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+            max_retry_after: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        RetryPolicy { max_retries: 0, ..Default::default() }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.min_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff)
+    }
+}
+
+impl BalanceClient {
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    // Sends a mutating request with an idempotency key (generated when None),
+    // retrying transport errors and 5xx with exponential backoff
+    pub async fn send_idempotent<F>(&self, key: Option<&str>, build: F) -> Result<BalanceOutcome, BalanceError>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        let key = match key {
+            Some(key) => key.to_string(),
+            None => crate::uuid::new().simple().to_string(),
+        };
+
+        let mut attempt = 0;
+        loop {
+            let mut retry_after = None;
+            let error = match build().header(IDEMPOTENCY_KEY_HEADER, &key).send().await {
+                Ok(resp) => {
+                    let status = resp.status();
+                    retry_after = resp
+                        .headers()
+                        .get(reqwest::header::RETRY_AFTER)
+                        .and_then(|h| h.to_str().ok())
+                        .and_then(|h| h.trim().parse::<u64>().ok())
+                        .map(Duration::from_secs);
+                    let replayed = resp
+                        .headers()
+                        .get(IDEMPOTENT_REPLAYED_HEADER)
+                        .is_some_and(|h| h.as_bytes().eq_ignore_ascii_case(b"true"));
+                    let body = resp.text().await.unwrap_or_default();
+
+                    if status.is_success() && replayed {
+                        return Ok(BalanceOutcome::Duplicate { key, body });
+                    }
+                    if status.is_success() {
+                        return Ok(BalanceOutcome::Applied { key, body });
+                    }
+                    // Rate limited requests weren't applied, they're retried like server errors
+                    if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS {
+                        return Ok(BalanceOutcome::Rejected { key, status, reason: body });
+                    }
+                    BalanceError::Server { key: key.clone(), status, body }
+                }
+                Err(err) => BalanceError::Unreachable { key: key.clone(), message: err.to_string() },
+            };
+
+            let too_long = retry_after.is_some_and(|wait| wait > self.retry.max_retry_after);
+            if attempt >= self.retry.max_retries || too_long {
+                eprintln!("{}", error);
+                return Err(error);
+            }
+
+            // The server's Retry-After wins over our own backoff when it's longer
+            let wait = self.retry.backoff(attempt).max(retry_after.unwrap_or_default());
+            eprintln!("{}, retrying in {:?}", error, wait);
+            tokio::time::sleep(wait).await;
+            attempt += 1;
+        }
+    }
+}
@@ -1,5 +1,9 @@
 use std::{collections::HashMap, fmt::Debug};
 
+use chrono::{DateTime, Utc};
+
+use crate::balance::{BalanceClient, BalanceError, BalanceOutcome};
+
 use reqwest::StatusCode;
 use rust_decimal::{Decimal, prelude::{FromPrimitive, ToPrimitive}};
 use rust_decimal_macros::dec;
@@ -7,11 +11,21 @@ use serde::{Deserialize, Serialize};
 
 use rust_decimal::RoundingStrategy;
 
-// use strum_macros::{Display, EnumString, AsRefStr};
+pub mod fees;
+pub mod ledger;
+pub mod money;
+pub mod rates;
+
+pub use fees::{FeeRule, Quote, Reservation, TransferError, TransferPolicy, Transfers};
+pub use ledger::MemoryLedger;
+#[cfg(feature = "db")]
+pub use ledger::PgLedger;
+pub use money::{Money, MoneyError};
+pub use rates::{RateError, RateProvider, StaticRates};
 
+// Codes and everything else about a currency live in `REGISTRY`. The
+// discriminant is the currency's id, `as` casts keep working.
 #[derive(Eq, Hash, PartialEq, Clone, Copy, Serialize, Deserialize)]
-// #[derive(Eq, Hash, PartialEq, Clone, Copy, Serialize, Deserialize, Display, EnumString, AsRefStr, Debug)]
-// #[strum(serialize_all = "UPPERCASE")]
 pub enum Currency {
     PRESTIGE = 1,
     STARS = 2,
@@ -20,183 +34,354 @@ pub enum Currency {
     USDT = 5,
 }
 
-// impl From<Currency> for u16 {
-//     fn from(value: Currency) -> u16 {
-//         match value {
-//             Currency::PRESTIGE => 1,
-//             Currency::STARS => 2,
-//             Currency::TICKETS => 3,
-//             Currency::TON => 4,
-//             Currency::USDT => 5,
-//         }
-//     }
-// }
+// Everything the conversions below know about a currency. Adding a currency
+// means adding its variant and its entry in `REGISTRY`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CurrencyInfo {
+    pub currency: Currency,
+    pub id: u16,       // stored as SMALLINT and used in balance service paths
+    pub code: &'static str,
+    pub decimals: Option<u32>, // None: not rounded, only normalized
+    pub units_decimals: u32,   // scale of the integer units `to_units` returns
+    pub symbol: &'static str,
+    pub transferable: bool,
+    pub default_usd: Option<Decimal>, // None: priced from the live TON rate
+}
+
+pub const REGISTRY: &[CurrencyInfo] = &[
+    CurrencyInfo {
+        currency: Currency::PRESTIGE,
+        id: 1,
+        code: "PRESTIGE",
+        decimals: Some(2),
+        units_decimals: 2,
+        symbol: "🏆",
+        transferable: true,
+        default_usd: Some(dec!(0.03)),
+    },
+    CurrencyInfo {
+        currency: Currency::STARS,
+        id: 2,
+        code: "STARS",
+        decimals: Some(0),
+        units_decimals: 0,
+        symbol: "⭐",
+        transferable: true,
+        default_usd: Some(dec!(0.015)),
+    },
+    CurrencyInfo {
+        currency: Currency::TICKETS,
+        id: 3,
+        code: "TICKETS",
+        decimals: None,
+        units_decimals: 0,
+        symbol: "🎟",
+        transferable: false,
+        default_usd: Some(dec!(0.1)),
+    },
+    CurrencyInfo {
+        currency: Currency::TON,
+        id: 4,
+        code: "TON",
+        decimals: Some(9),
+        units_decimals: 9,
+        symbol: "💎",
+        transferable: true,
+        default_usd: None,
+    },
+    CurrencyInfo {
+        currency: Currency::USDT,
+        id: 5,
+        code: "USDT",
+        decimals: Some(2),
+        units_decimals: 0,
+        symbol: "$",
+        transferable: true,
+        default_usd: Some(dec!(1.0)),
+    },
+];
+
+// Ids are stored and sent to the balance service, two currencies can't share one
+// and an entry's id has to be its variant's discriminant
+const _: () = {
+    let mut i = 0;
+    while i < REGISTRY.len() {
+        assert!(REGISTRY[i].id == REGISTRY[i].currency as u16, "REGISTRY id differs from the discriminant");
+        let mut j = i + 1;
+        while j < REGISTRY.len() {
+            assert!(REGISTRY[i].id != REGISTRY[j].id, "duplicate currency id in REGISTRY");
+            j += 1;
+        }
+        i += 1;
+    }
+};
+
+// Every variant has a REGISTRY entry, so `info` can't fail at runtime
+const _: () = {
+    const fn registered(currency: Currency) -> bool {
+        let mut i = 0;
+        while i < REGISTRY.len() {
+            if REGISTRY[i].currency as u16 == currency as u16 {
+                return true;
+            }
+            i += 1;
+        }
+        false
+    }
+
+    // Exhaustive, so a new variant doesn't compile until it's added to the list below
+    const fn variant(currency: Currency) -> Currency {
+        match currency {
+            Currency::PRESTIGE | Currency::STARS | Currency::TICKETS | Currency::TON | Currency::USDT => currency,
+        }
+    }
+
+    let variants = [Currency::PRESTIGE, Currency::STARS, Currency::TICKETS, Currency::TON, Currency::USDT];
+    let mut i = 0;
+    while i < variants.len() {
+        assert!(registered(variant(variants[i])), "currency without a REGISTRY entry");
+        i += 1;
+    }
+};
+
+impl Currency {
+    // Every registered currency, in registry order
+    pub const ALL: [Currency; REGISTRY.len()] = {
+        let mut all = [Currency::PRESTIGE; REGISTRY.len()];
+        let mut i = 0;
+        while i < REGISTRY.len() {
+            all[i] = REGISTRY[i].currency;
+            i += 1;
+        }
+        all
+    };
+
+    pub fn info(&self) -> &'static CurrencyInfo {
+        REGISTRY
+            .iter()
+            .find(|info| info.currency == *self)
+            .expect("every currency has a registry entry")
+    }
+
+    pub fn from_id(id: u16) -> Option<Currency> {
+        REGISTRY.iter().find(|info| info.id == id).map(|info| info.currency)
+    }
+
+    // Exact code, e.g. "STARS"
+    pub fn from_code(code: &str) -> Option<Currency> {
+        REGISTRY.iter().find(|info| info.code == code).map(|info| info.currency)
+    }
+
+    pub fn id(&self) -> u16 {
+        self.info().id
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.info().code
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        self.info().symbol
+    }
+
+    pub fn is_transferable(&self) -> bool {
+        self.info().transferable
+    }
+
+    // Decimal places amounts are rounded to, None for TICKETS which are only normalized
+    pub fn decimals(&self) -> Option<u32> {
+        self.info().decimals
+    }
+
+    // Decimal places of the minor unit: nanotons for TON, cents for PRESTIGE/USDT
+    pub fn minor_decimals(&self) -> u32 {
+        self.decimals().unwrap_or(0)
+    }
+}
 
 impl Debug for Currency {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Currency::STARS => write!(f, "STARS"),
-            Currency::TON => write!(f, "TON"),
-            Currency::USDT => write!(f, "USDT"),
-            Currency::PRESTIGE => write!(f, "PRESTIGE"),
-            Currency::TICKETS => write!(f, "TICKETS"),
-        }
+        f.write_str(self.code())
+    }
+}
+
+impl std::fmt::Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl AsRef<str> for Currency {
+    fn as_ref(&self) -> &str {
+        self.code()
     }
 }
 
 impl TryFrom<&str> for Currency {
     type Error = ();
     fn try_from(s: &str) -> Result<Self, ()> {
-        Ok(match s {
-            "PRESTIGE" => Currency::PRESTIGE,
-            "STARS" => Currency::STARS,
-            "TICKETS" => Currency::TICKETS,
-            "TON" => Currency::TON,
-            "USDT" => Currency::USDT,
-            _ => return Err(()),
-        })
+        Currency::from_code(s).ok_or(())
     }
 }
 
-impl AsRef<str> for Currency {
-    fn as_ref(&self) -> &str {
-        match self {
-            Currency::PRESTIGE => "PRESTIGE",
-            Currency::STARS => "STARS",
-            Currency::TICKETS => "TICKETS",
-            Currency::TON => "TON",
-            Currency::USDT => "USDT",
-        }
+// Case-insensitive, unlike `TryFrom<&str>`
+impl TryFrom<String> for Currency {
+    type Error = ();
+    fn try_from(value: String) -> Result<Self, ()> {
+        Currency::from_code(&value.to_uppercase()).ok_or(())
     }
 }
 
 impl TryFrom<u16> for Currency {
     type Error = ();
     fn try_from(value: u16) -> Result<Self, ()> {
-        Ok(match value {
-            1 => Currency::PRESTIGE,
-            2 => Currency::STARS,
-            3 => Currency::TICKETS,
-            4 => Currency::TON,
-            5 => Currency::USDT,
-            _ => return Err(()),
-        })
+        Currency::from_id(value).ok_or(())
     }
 }
 
-impl Into<u16> for Currency {
-    fn into(self) -> u16 {
-        match self {
-            Currency::PRESTIGE => 1,
-            Currency::STARS => 2,
-            Currency::TICKETS => 3,
-            Currency::TON => 4,
-            Currency::USDT => 5,
-        }
+impl From<Currency> for u16 {
+    fn from(currency: Currency) -> u16 {
+        currency.id()
     }
 }
 
-impl TryFrom<String> for Currency {
-    type Error = ();
-    fn try_from(value: String) -> Result<Self, ()> {
-        Ok(match value.to_uppercase().as_str() {
-            "PRESTIGE" => Currency::PRESTIGE,
-            "STARS" => Currency::STARS,
-            "TICKETS" => Currency::TICKETS,
-            "TON" => Currency::TON,
-            "USDT" => Currency::USDT,
-            _ => return Err(()),
-        })
+// SMALLINT column holding the registry id, so rows and binds use `Currency` directly
+#[cfg(feature = "db")]
+mod sql {
+    use sqlx::{
+        Decode, Encode, Postgres, Type,
+        encode::IsNull,
+        error::BoxDynError,
+        postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef},
+    };
+
+    use super::Currency;
+
+    impl Type<Postgres> for Currency {
+        fn type_info() -> PgTypeInfo {
+            <i16 as Type<Postgres>>::type_info()
+        }
+
+        fn compatible(ty: &PgTypeInfo) -> bool {
+            <i16 as Type<Postgres>>::compatible(ty)
+        }
     }
-}
 
-impl std::fmt::Display for Currency {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let currency_str = match self {
-            Currency::PRESTIGE => "PRESTIGE",
-            Currency::STARS => "STARS",
-            Currency::TICKETS => "TICKETS",
-            Currency::TON => "TON",
-            Currency::USDT => "USDT",
-        };
+    impl Encode<'_, Postgres> for Currency {
+        fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+            <i16 as Encode<Postgres>>::encode_by_ref(&(self.id() as i16), buf)
+        }
+    }
 
-        return write!(f, "{}", currency_str);
+    impl Decode<'_, Postgres> for Currency {
+        fn decode(value: PgValueRef<'_>) -> Result<Self, BoxDynError> {
+            let id = <i16 as Decode<Postgres>>::decode(value)?;
+            u16::try_from(id)
+                .ok()
+                .and_then(Currency::from_id)
+                .ok_or_else(|| format!("Unknown currency {}", id).into())
+        }
     }
 }
 
+// For ids taken from requests, columns decode to `Currency` directly with the db feature
 pub fn parse(id: i16) -> Result<Currency, (StatusCode, String)> {
     u16::try_from(id).ok()
-        .and_then(|v| Currency::try_from(v).ok())
+        .and_then(Currency::from_id)
         .ok_or((StatusCode::BAD_REQUEST, "Unknown currency".into()))
 }
 
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RoundMode {
     Nearest, // Standard rounding: .5 up, .4 down
     Down,    // Floor: Always rounds towards zero
     Up,      // Ceil: Always rounds away from zero
 }
 
-pub fn convert(
+impl RoundMode {
+    pub fn strategy(&self) -> RoundingStrategy {
+        match self {
+            RoundMode::Nearest => RoundingStrategy::MidpointAwayFromZero,
+            RoundMode::Down => RoundingStrategy::ToZero,
+            RoundMode::Up => RoundingStrategy::AwayFromZero,
+        }
+    }
+}
+
+pub async fn convert<R: RateProvider>(
     price: Decimal,
     from: &Currency,
     to: &Currency,
-    ton_price: Decimal,
+    rates: &R,
     round_mode: RoundMode,
-) -> Decimal {
+) -> Result<Decimal, RateError> {
     if from == to {
-        return price;
+        return Ok(price);
     }
-    let from_course = get_course(from, ton_price);
-    let to_course = get_course(to, ton_price);
+    let from_course = rates.rate(from).await?;
+    let to_course = rates.rate(to).await?;
 
-    let mut result = (price * from_course) / to_course;
+    apply_courses(price, from_course, to_course, to, round_mode)
+}
+
+// `convert` at the rates in effect at `at`, for historic amounts
+pub async fn convert_at<R: RateProvider>(
+    price: Decimal,
+    from: &Currency,
+    to: &Currency,
+    rates: &R,
+    at: DateTime<Utc>,
+    round_mode: RoundMode,
+) -> Result<Decimal, RateError> {
+    if from == to {
+        return Ok(price);
+    }
+    let from_course = rates.rate_at(from, at).await?;
+    let to_course = rates.rate_at(to, at).await?;
+
+    apply_courses(price, from_course, to_course, to, round_mode)
+}
+
+fn apply_courses(
+    price: Decimal,
+    from_course: Decimal,
+    to_course: Decimal,
+    to: &Currency,
+    round_mode: RoundMode,
+) -> Result<Decimal, RateError> {
+    // A zero rate or an overflow is a bad rate source, not a reason to panic
+    let result = price
+        .checked_mul(from_course)
+        .and_then(|usd| usd.checked_div(to_course))
+        .ok_or_else(|| RateError::Source(format!("Can't convert {} at rates {} / {}", price, from_course, to_course)))?;
 
     // return Number(result.toFixed(2));
     // result.with_scale(2)
     // result.normalize()
     // to_units(result, to)
 
-    // Map your custom RoundMode to rust_decimal::RoundingStrategy
-    let strategy = match round_mode {
-        RoundMode::Nearest => RoundingStrategy::MidpointAwayFromZero,
-        RoundMode::Down => RoundingStrategy::ToZero,
-        RoundMode::Up => RoundingStrategy::AwayFromZero,
-    };
-
-    result = match to {
-        Currency::STARS => result.round_dp_with_strategy(0, strategy),
-        Currency::TON => result.round_dp_with_strategy(9, strategy),
-        Currency::PRESTIGE | Currency::USDT => result.round_dp_with_strategy(2, strategy),
-        _ => result.normalize(),
-    };
+    Ok(Money::new(result, *to).round(round_mode).amount())
+}
 
-    result
+// `convert` for Money, the target precision comes from `to`
+pub async fn convert_money<R: RateProvider>(money: &Money, to: &Currency, rates: &R, round_mode: RoundMode) -> Result<Money, RateError> {
+    let amount = convert(money.amount(), &money.currency(), to, rates, round_mode).await?;
+    Ok(Money::new(amount, *to))
 }
 
+// Integer units the balance service expects, truncated: 1.0 TON -> 1,000,000,000
+// nanotons, 10.50 PRESTIGE -> 1050, whole units for the rest (see `units_decimals`).
+// See `Money::to_minor` for minor units with a chosen rounding.
 pub fn to_units(amount: Decimal, currency: &Currency) -> i64 {
-    match currency {
-        // TON: 9 decimals. 1.0 TON -> 1,000,000,000 nanotons
-        Currency::TON => (amount * dec!(1_000_000_000)).to_i64().unwrap_or(0),
-        
-        // STARS: 0 decimals. 50.0 STARS -> 50
-        Currency::STARS => amount.to_i64().unwrap_or(0),
-        
-        // PRESTIGE: 2 decimals. 10.50 -> 1050 (if your service uses cents)
-        Currency::PRESTIGE => (amount * dec!(100)).to_i64().unwrap_or(0),
-        
-        _ => amount.to_i64().unwrap_or(0),
-    }
+    let scale = Decimal::from(10i64.pow(currency.info().units_decimals));
+    amount.checked_mul(scale).and_then(|units| units.to_i64()).unwrap_or(0)
 }
 
+// Default USD courses from the registry, TON at `current_ton_usd`.
+// See `StaticRates` and the other `RateProvider`s
 pub fn get_course(currency: &Currency, current_ton_usd: Decimal) -> Decimal {
-    match currency {
-        Currency::TON => current_ton_usd,
-        Currency::PRESTIGE => dec!(0.03),
-        Currency::STARS => dec!(0.015),
-        Currency::TICKETS => dec!(0.1),
-        Currency::USDT => dec!(1.0),
-    }
+    currency.info().default_usd.unwrap_or(current_ton_usd)
 }
 
 // pub fn from_nanoton(ton: u64) -> Decimal {
@@ -210,44 +395,172 @@ pub fn from_nanoton(nanoton: u64) -> Decimal {
     Decimal::from_i128_with_scale(nanoton as i128, 9)
 }
 
+// Mutating calls send an idempotency key (a fresh one when `key` is None) and
+// are retried on transport errors and 5xx, so pass the same key when retrying
+// a call that failed with a `BalanceError`
+impl BalanceClient {
+    pub async fn add_multiple(
+        &self,
+        currencies: &HashMap<Currency, Decimal>,
+        user_id: i64,
+        key: Option<&str>,
+    ) -> Result<BalanceOutcome, BalanceError> {
+        self.send_idempotent(key, || {
+            self.post(&format!("balance/add_currencies/{}", user_id))
+                .json(currencies)
+        })
+        .await
+    }
+
+    pub async fn add(
+        &self,
+        currency: &Currency,
+        amount: &Decimal,
+        user_id: i64,
+        is_deposit: bool,
+        key: Option<&str>,
+    ) -> Result<BalanceOutcome, BalanceError> {
+        self.send_idempotent(key, || {
+            self.post(&format!("balance/add_currency/{}/{}/{}", currency.id(), amount, user_id))
+                .header("Is-Deposit", if is_deposit {"true"} else {"false"})
+        })
+        .await
+    }
+
+    pub async fn sub(
+        &self,
+        currency: &Currency,
+        amount: &Decimal,
+        user_id: i64,
+        key: Option<&str>,
+    ) -> Result<BalanceOutcome, BalanceError> {
+        self.send_idempotent(key, || {
+            self.post(&format!("balance/sub_currency/{}/{}/{}", currency.id(), amount, user_id))
+        })
+        .await
+    }
+
+    // The outcome body is the service's transfer response
+    pub async fn transfer(
+        &self,
+        currency: &Currency,
+        amount: &Decimal,
+        user_id: i64,
+        receiver_id: i64,
+        fee: &Decimal,
+        key: Option<&str>,
+    ) -> Result<BalanceOutcome, BalanceError> {
+        self.send_idempotent(key, || {
+            self.post(&format!(
+                "balance/transfer/{}/{}/{}/{}/{}",
+                currency.id(), amount, user_id, receiver_id, fee,
+            ))
+        })
+        .await
+    }
+}
+
+// Balance operations shared by the remote `BalanceClient` and the local ledgers,
+// so a service can switch backends without touching its handlers
+pub trait BalanceBackend: Send + Sync {
+    fn add_multiple(
+        &self,
+        currencies: &HashMap<Currency, Decimal>,
+        user_id: i64,
+        key: Option<&str>,
+    ) -> impl Future<Output = Result<BalanceOutcome, BalanceError>> + Send;
+
+    fn add(
+        &self,
+        currency: &Currency,
+        amount: &Decimal,
+        user_id: i64,
+        is_deposit: bool,
+        key: Option<&str>,
+    ) -> impl Future<Output = Result<BalanceOutcome, BalanceError>> + Send;
+
+    fn sub(
+        &self,
+        currency: &Currency,
+        amount: &Decimal,
+        user_id: i64,
+        key: Option<&str>,
+    ) -> impl Future<Output = Result<BalanceOutcome, BalanceError>> + Send;
+
+    fn transfer(
+        &self,
+        currency: &Currency,
+        amount: &Decimal,
+        user_id: i64,
+        receiver_id: i64,
+        fee: &Decimal,
+        key: Option<&str>,
+    ) -> impl Future<Output = Result<BalanceOutcome, BalanceError>> + Send;
+}
+
+impl BalanceBackend for BalanceClient {
+    async fn add_multiple(
+        &self,
+        currencies: &HashMap<Currency, Decimal>,
+        user_id: i64,
+        key: Option<&str>,
+    ) -> Result<BalanceOutcome, BalanceError> {
+        BalanceClient::add_multiple(self, currencies, user_id, key).await
+    }
+
+    async fn add(
+        &self,
+        currency: &Currency,
+        amount: &Decimal,
+        user_id: i64,
+        is_deposit: bool,
+        key: Option<&str>,
+    ) -> Result<BalanceOutcome, BalanceError> {
+        BalanceClient::add(self, currency, amount, user_id, is_deposit, key).await
+    }
+
+    async fn sub(&self, currency: &Currency, amount: &Decimal, user_id: i64, key: Option<&str>) -> Result<BalanceOutcome, BalanceError> {
+        BalanceClient::sub(self, currency, amount, user_id, key).await
+    }
+
+    async fn transfer(
+        &self,
+        currency: &Currency,
+        amount: &Decimal,
+        user_id: i64,
+        receiver_id: i64,
+        fee: &Decimal,
+        key: Option<&str>,
+    ) -> Result<BalanceOutcome, BalanceError> {
+        BalanceClient::transfer(self, currency, amount, user_id, receiver_id, fee, key).await
+    }
+}
+
+// Maps a typed result back to the old `(StatusCode, String)` shape of the free functions
+fn legacy_result(result: Result<BalanceOutcome, BalanceError>) -> Result<String, (StatusCode, String)> {
+    match result {
+        Ok(BalanceOutcome::Applied { body, .. } | BalanceOutcome::Duplicate { body, .. }) => Ok(body),
+        Ok(BalanceOutcome::Rejected { status, reason, .. }) => Err((status, reason)),
+        Err(err @ BalanceError::Unreachable { .. }) => Err((StatusCode::BAD_GATEWAY, err.to_string())),
+        Err(err @ (BalanceError::Server { .. } | BalanceError::Storage { .. })) => {
+            Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+        }
+    }
+}
+
+// The free functions below go through a `BalanceClient` at BALANCE_SERVICE_URL
+// (production by default), prefer holding a `BalanceClient` instead
+
 pub async fn add_multiple(
     currencies: &HashMap<Currency, Decimal>,
     user_id: i64,
     client: &reqwest::Client,
-    internal_secret: &String,
+    internal_secret: &str,
 ) -> Result<(), (StatusCode, String)> {
-    let prestige_url = "https://prestige.up.railway.app";
-
-    // 1. Keep the URL clean (only ID in path)
-    let external_url = format!("{}/balance/add_currencies/{}", prestige_url, user_id);
-    
-    // let external_url = format!(
-    //     "{}/balance/add_currencies/{}/{}", 
-    //     prestige_url, serde_json::to_string(currencies).unwrap(), user_id
-    // );
-    
-    let resp = client
-        .post(&external_url)
-        .header("X-Internal-Secret", internal_secret)
-        .json(currencies)
-        .send()
-        .await
-        .map_err(|e| {
-            eprintln!("Network Error: {:?}", e);
-            (StatusCode::BAD_GATEWAY, "Balance service unreachable".to_string())
-        })?;
-    
-    if !resp.status().is_success() {
-        let err_status = resp.status();
-        let err_body = resp.text().await.unwrap_or_else(|_| "No error body".to_string());
-        
-        let message = format!("Balance service error ({}): {}", err_status, err_body);
-        eprintln!("{}", message);
-
-        return Err((StatusCode::INTERNAL_SERVER_ERROR, message));
-    }
-
-    Ok(())
+    let result = BalanceClient::with_secret(client, internal_secret)
+        .add_multiple(currencies, user_id, None)
+        .await;
+    legacy_result(result).map(|_| ())
 }
 
 pub async fn add(
@@ -256,36 +569,13 @@ pub async fn add(
     user_id: i64,
 
     client: &reqwest::Client,
-    internal_secret: &String,
+    internal_secret: &str,
     is_deposit: bool
 ) -> Result<(), (StatusCode, String)> {
-    let prestige_url = "https://prestige.up.railway.app";
-
-    let currency_id: u16 = (*currency).into();
-    let external_url = format!(
-        "{}/balance/add_currency/{}/{}/{}",
-        prestige_url, currency_id, amount, user_id
-    );
-
-    // let tasker_host = env::get("TASKER_HOST").unwrap_or_default();
-    
-    let resp = client
-        .post(&external_url)
-        .header("X-Internal-Secret", internal_secret)
-        .header("Is-Deposit", if is_deposit {"true"} else {"false"})
-        // .header("Tasker-Host", tasker_host)
-        .send()
-        .await
-        .map_err(|_| (StatusCode::BAD_GATEWAY, "Balance service unreachable".to_string()))?;
-    
-    if !resp.status().is_success() {
-        let err = resp.text().await.unwrap_or_default();
-        let message = format!("External add_currency failed: {}", err);
-        println!("{}", message);
-        return Err((StatusCode::INTERNAL_SERVER_ERROR, message));
-    }
-
-    Ok(())
+    let result = BalanceClient::with_secret(client, internal_secret)
+        .add(currency, amount, user_id, is_deposit, None)
+        .await;
+    legacy_result(result).map(|_| ())
 }
 
 pub async fn sub(
@@ -294,37 +584,12 @@ pub async fn sub(
     user_id: i64,
 
     client: &reqwest::Client,
-    internal_secret: &String,
+    internal_secret: &str,
 ) -> Result<(), (StatusCode, String)> {
-    let prestige_url = "https://prestige.up.railway.app";
-
-    let currency_id: u16 = (*currency).into();
-    let external_url = format!(
-        "{}/balance/sub_currency/{}/{}/{}", 
-        prestige_url, currency_id, amount, user_id
-    );
-    
-    // let tasker_host = env::get("TASKER_HOST").unwrap_or_default();
-
-    let resp = client
-        .post(&external_url)
-        .header("X-Internal-Secret", internal_secret)
-        // .header("Tasker-Host", tasker_host)
-        .send()
-        .await
-        .map_err(|e| {
-            eprintln!("Network Error: {:?}", e);
-            (StatusCode::BAD_GATEWAY, "Balance service unreachable".to_string())
-        })?;
-    
-    if !resp.status().is_success() {
-        let err = resp.text().await.unwrap_or_default();
-        let message = format!("External sub_currency failed: {}", err);
-        println!("{}", message);
-        return Err((StatusCode::INTERNAL_SERVER_ERROR, message));
-    }
-
-    Ok(())
+    let result = BalanceClient::with_secret(client, internal_secret)
+        .sub(currency, amount, user_id, None)
+        .await;
+    legacy_result(result).map(|_| ())
 }
 
 pub fn f32_to_decimal(value: f32) -> Option<Decimal> {
@@ -343,6 +608,7 @@ pub fn i64_to_decimal(value: i64) -> Option<Decimal> {
     Decimal::from_i64(value)
 }
 
+// Takes the fee as given, see `Transfers` to apply a `TransferPolicy`
 pub async fn transfer(
     currency: &Currency,
     amount: &Decimal,
@@ -352,43 +618,10 @@ pub async fn transfer(
     fee: &Decimal,
 
     client: &reqwest::Client,
-    internal_secret: &String,
+    internal_secret: &str,
 ) -> Result<String, (StatusCode, String)> {
-    let prestige_url = "https://prestige.up.railway.app";
-
-    let currency_id: u16 = (*currency).into();
-    let external_url = format!(
-        "{}/balance/transfer/{}/{}/{}/{}/{}", 
-        prestige_url, currency_id, amount, user_id, receiver_id, fee,
-    );
-    
-    // let tasker_host = env::get("TASKER_HOST").unwrap_or_default();
-
-    let resp = client
-        .post(&external_url)
-        .header("X-Internal-Secret", internal_secret)
-        // .header("Tasker-Host", tasker_host)
-        .send()
-        .await
-        .map_err(|e| {
-            eprintln!("Network Error: {:?}", e);
-            (StatusCode::BAD_GATEWAY, "Balance service unreachable".to_string())
-        })?;
-    
-    if !resp.status().is_success() {
-        let err = resp.text().await.unwrap_or_default();
-        let message = format!("External transfer failed: {}", err);
-        println!("{}", message);
-        return Err((StatusCode::INTERNAL_SERVER_ERROR, message));
-    }
-
-    let result = resp.text().await;
-    match result {
-        Ok(text) => Ok(text),
-        Err(e) => {
-            let message = format!("Failed to read transfer response: {:?}", e);
-            println!("{}", message);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, message))
-        }
-    }
+    let result = BalanceClient::with_secret(client, internal_secret)
+        .transfer(currency, amount, user_id, receiver_id, fee, None)
+        .await;
+    legacy_result(result)
 }
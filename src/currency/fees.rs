@@ -0,0 +1,530 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use chrono::{NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::{BalanceBackend, Currency, Money, RoundMode};
+use crate::balance::{BalanceError, BalanceOutcome};
+
+// percent% of the amount plus `flat`, clamped to [min, max]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FeeRule {
+    pub percent: Decimal,
+    pub flat: Decimal,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+}
+
+impl FeeRule {
+    pub fn free() -> Self {
+        Self::default()
+    }
+
+    pub fn percent(percent: Decimal) -> Self {
+        FeeRule { percent, ..Default::default() }
+    }
+
+    pub fn flat(flat: Decimal) -> Self {
+        FeeRule { flat, ..Default::default() }
+    }
+
+    pub fn plus_flat(mut self, flat: Decimal) -> Self {
+        self.flat = flat;
+        self
+    }
+
+    pub fn min(mut self, min: Decimal) -> Self {
+        self.min = Some(min);
+        self
+    }
+
+    pub fn max(mut self, max: Decimal) -> Self {
+        self.max = Some(max);
+        self
+    }
+
+    // Rounded up to the currency's precision so fractional fees aren't lost
+    pub fn fee(&self, amount: Decimal, currency: &Currency) -> Decimal {
+        let mut fee = amount * self.percent / Decimal::ONE_HUNDRED + self.flat;
+        if let Some(min) = self.min {
+            fee = fee.max(min);
+        }
+        if let Some(max) = self.max {
+            fee = fee.min(max);
+        }
+        Money::new(fee, *currency).round(RoundMode::Up).amount()
+    }
+}
+
+// What a transfer will cost before it's executed: the sender pays `amount`,
+// the receiver gets `net`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Quote {
+    pub currency: Currency,
+    pub amount: Decimal,
+    pub fee: Decimal,
+    pub net: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferError {
+    NotTransferable(Currency),
+    InvalidAmount,
+    // The fee would eat the whole amount
+    BelowFee { fee: Decimal },
+    DailyLimit { limit: Decimal, remaining: Decimal },
+    Usage(String),
+    Balance(BalanceError),
+}
+
+impl std::fmt::Display for TransferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransferError::NotTransferable(currency) => write!(f, "{} can't be transferred", currency),
+            TransferError::InvalidAmount => write!(f, "Amount must be positive"),
+            TransferError::BelowFee { fee } => write!(f, "Amount must be greater than the fee ({})", fee),
+            TransferError::DailyLimit { limit, remaining } => {
+                write!(f, "Daily transfer limit of {} reached, {} left today", limit, remaining)
+            }
+            TransferError::Usage(message) => write!(f, "Failed to check transfer limits: {}", message),
+            TransferError::Balance(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reservation {
+    Reserved,
+    // The idempotency key already holds a reservation today, a retry doesn't count twice
+    Held,
+    OverLimit,
+}
+
+// Amount each user sent per currency per UTC day, for the daily limits
+pub trait TransferUsage: Send + Sync {
+    fn sent(&self, user_id: i64, currency: &Currency, day: NaiveDate) -> impl Future<Output = Result<Decimal, String>> + Send;
+
+    // Adds `amount` to the day's usage only if it stays within `limit`, in one step
+    // so concurrent transfers can't both pass. `key` is remembered with the
+    // reservation, so retries of the same transfer reach the balance backend.
+    fn reserve(
+        &self,
+        user_id: i64,
+        currency: &Currency,
+        day: NaiveDate,
+        amount: Decimal,
+        limit: Decimal,
+        key: Option<&str>,
+    ) -> impl Future<Output = Result<Reservation, String>> + Send;
+
+    // Gives back a reservation whose transfer didn't go through
+    fn release(
+        &self,
+        user_id: i64,
+        currency: &Currency,
+        day: NaiveDate,
+        amount: Decimal,
+        key: Option<&str>,
+    ) -> impl Future<Output = Result<(), String>> + Send;
+}
+
+#[derive(Default)]
+struct Usage {
+    sent: HashMap<(i64, Currency, NaiveDate), Decimal>,
+    keys: HashSet<(i64, Currency, NaiveDate, String)>,
+}
+
+#[derive(Default)]
+pub struct MemoryTransferUsage {
+    usage: Mutex<Usage>,
+}
+
+impl MemoryTransferUsage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TransferUsage for MemoryTransferUsage {
+    async fn sent(&self, user_id: i64, currency: &Currency, day: NaiveDate) -> Result<Decimal, String> {
+        let usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(usage.sent.get(&(user_id, *currency, day)).copied().unwrap_or(Decimal::ZERO))
+    }
+
+    async fn reserve(
+        &self,
+        user_id: i64,
+        currency: &Currency,
+        day: NaiveDate,
+        amount: Decimal,
+        limit: Decimal,
+        key: Option<&str>,
+    ) -> Result<Reservation, String> {
+        // Checked and added under one lock
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        usage.sent.retain(|(_, _, sent_day), _| *sent_day >= day);
+        usage.keys.retain(|(_, _, key_day, _)| *key_day >= day);
+
+        let key = key.map(|key| (user_id, *currency, day, key.to_string()));
+        if let Some(key) = &key
+            && usage.keys.contains(key)
+        {
+            return Ok(Reservation::Held);
+        }
+
+        let used = usage.sent.entry((user_id, *currency, day)).or_insert(Decimal::ZERO);
+        if *used + amount > limit {
+            return Ok(Reservation::OverLimit);
+        }
+        *used += amount;
+        usage.keys.extend(key);
+        Ok(Reservation::Reserved)
+    }
+
+    async fn release(
+        &self,
+        user_id: i64,
+        currency: &Currency,
+        day: NaiveDate,
+        amount: Decimal,
+        key: Option<&str>,
+    ) -> Result<(), String> {
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(key) = key {
+            usage.keys.remove(&(user_id, *currency, day, key.to_string()));
+        }
+        if let Some(used) = usage.sent.get_mut(&(user_id, *currency, day)) {
+            *used = (*used - amount).max(Decimal::ZERO);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "db")]
+pub struct PgTransferUsage {
+    pool: crate::db::Pool,
+}
+
+#[cfg(feature = "db")]
+impl PgTransferUsage {
+    pub fn new(pool: crate::db::Pool) -> Self {
+        PgTransferUsage { pool }
+    }
+
+    pub async fn create_table(&self) -> Result<(), crate::db::StdError> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS transfer_usage (
+                user_id BIGINT NOT NULL,
+                currency SMALLINT NOT NULL,
+                day DATE NOT NULL,
+                amount NUMERIC NOT NULL DEFAULT 0,
+                PRIMARY KEY (user_id, currency, day)
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS transfer_usage_keys (
+                user_id BIGINT NOT NULL,
+                currency SMALLINT NOT NULL,
+                day DATE NOT NULL,
+                key TEXT NOT NULL,
+                PRIMARY KEY (user_id, currency, day, key)
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "db")]
+impl TransferUsage for PgTransferUsage {
+    async fn sent(&self, user_id: i64, currency: &Currency, day: NaiveDate) -> Result<Decimal, String> {
+        let sent = sqlx::query_scalar::<_, Decimal>(
+            "SELECT amount FROM transfer_usage WHERE user_id = $1 AND currency = $2 AND day = $3",
+        )
+        .bind(user_id)
+        .bind(currency)
+        .bind(day)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        Ok(sent.unwrap_or(Decimal::ZERO))
+    }
+
+    async fn reserve(
+        &self,
+        user_id: i64,
+        currency: &Currency,
+        day: NaiveDate,
+        amount: Decimal,
+        limit: Decimal,
+        key: Option<&str>,
+    ) -> Result<Reservation, String> {
+        let mut tx = self.pool.begin().await.map_err(|e| e.to_string())?;
+
+        // A concurrent retry with the same key waits here until this one commits
+        if let Some(key) = key {
+            let claimed = sqlx::query_scalar::<_, String>(
+                r#"
+                INSERT INTO transfer_usage_keys (user_id, currency, day, key) VALUES ($1, $2, $3, $4)
+                ON CONFLICT DO NOTHING
+                RETURNING key
+                "#,
+            )
+            .bind(user_id)
+            .bind(currency)
+            .bind(day)
+            .bind(key)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+
+            if claimed.is_none() {
+                return Ok(Reservation::Held);
+            }
+        }
+
+        // The conflicting row is locked, so the check and the increment can't interleave
+        let reserved = sqlx::query_scalar::<_, Decimal>(
+            r#"
+            INSERT INTO transfer_usage (user_id, currency, day, amount)
+            SELECT $1, $2, $3, $4 WHERE $4 <= $5
+            ON CONFLICT (user_id, currency, day) DO UPDATE SET amount = transfer_usage.amount + EXCLUDED.amount
+            WHERE transfer_usage.amount + EXCLUDED.amount <= $5
+            RETURNING amount
+            "#,
+        )
+        .bind(user_id)
+        .bind(currency)
+        .bind(day)
+        .bind(amount)
+        .bind(limit)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        // Dropping the transaction rolls back the key as well
+        if reserved.is_none() {
+            return Ok(Reservation::OverLimit);
+        }
+        tx.commit().await.map_err(|e| e.to_string())?;
+        Ok(Reservation::Reserved)
+    }
+
+    async fn release(
+        &self,
+        user_id: i64,
+        currency: &Currency,
+        day: NaiveDate,
+        amount: Decimal,
+        key: Option<&str>,
+    ) -> Result<(), String> {
+        let mut tx = self.pool.begin().await.map_err(|e| e.to_string())?;
+
+        if let Some(key) = key {
+            sqlx::query("DELETE FROM transfer_usage_keys WHERE user_id = $1 AND currency = $2 AND day = $3 AND key = $4")
+                .bind(user_id)
+                .bind(currency)
+                .bind(day)
+                .bind(key)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        sqlx::query(
+            r#"
+            UPDATE transfer_usage SET amount = GREATEST(amount - $4, 0)
+            WHERE user_id = $1 AND currency = $2 AND day = $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(currency)
+        .bind(day)
+        .bind(amount)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+// The transfer rules every service applies: fees per currency, non-transferable
+// currencies and per-user daily limits on the amount sent
+#[derive(Debug, Clone)]
+pub struct TransferPolicy {
+    default_fee: FeeRule,
+    fees: HashMap<Currency, FeeRule>,
+    daily_limits: HashMap<Currency, Decimal>,
+    blocked: HashSet<Currency>,
+}
+
+impl Default for TransferPolicy {
+    // Free transfers, currencies the registry marks non-transferable blocked
+    fn default() -> Self {
+        Currency::ALL
+            .into_iter()
+            .filter(|currency| !currency.is_transferable())
+            .fold(TransferPolicy::new(), TransferPolicy::block)
+    }
+}
+
+impl TransferPolicy {
+    // No fees, limits or blocked currencies
+    pub fn new() -> Self {
+        TransferPolicy {
+            default_fee: FeeRule::free(),
+            fees: HashMap::new(),
+            daily_limits: HashMap::new(),
+            blocked: HashSet::new(),
+        }
+    }
+
+    // Rule for currencies without their own
+    pub fn default_fee(mut self, rule: FeeRule) -> Self {
+        self.default_fee = rule;
+        self
+    }
+
+    pub fn fee(mut self, currency: Currency, rule: FeeRule) -> Self {
+        self.fees.insert(currency, rule);
+        self
+    }
+
+    pub fn daily_limit(mut self, currency: Currency, limit: Decimal) -> Self {
+        self.daily_limits.insert(currency, limit);
+        self
+    }
+
+    pub fn block(mut self, currency: Currency) -> Self {
+        self.blocked.insert(currency);
+        self
+    }
+
+    pub fn is_transferable(&self, currency: &Currency) -> bool {
+        !self.blocked.contains(currency)
+    }
+
+    pub fn fee_rule(&self, currency: &Currency) -> &FeeRule {
+        self.fees.get(currency).unwrap_or(&self.default_fee)
+    }
+
+    // Fee and net for `amount`, without the daily limit
+    pub fn quote(&self, currency: &Currency, amount: Decimal) -> Result<Quote, TransferError> {
+        if !self.is_transferable(currency) {
+            return Err(TransferError::NotTransferable(*currency));
+        }
+        if amount <= Decimal::ZERO {
+            return Err(TransferError::InvalidAmount);
+        }
+
+        let fee = self.fee_rule(currency).fee(amount, currency);
+        if fee >= amount {
+            return Err(TransferError::BelowFee { fee });
+        }
+
+        Ok(Quote { currency: *currency, amount, fee, net: amount - fee })
+    }
+}
+
+// A policy applied to a balance backend, with the usage its daily limits count against
+pub struct Transfers<B, U> {
+    policy: TransferPolicy,
+    backend: B,
+    usage: U,
+}
+
+impl<B: BalanceBackend, U: TransferUsage> Transfers<B, U> {
+    pub fn new(policy: TransferPolicy, backend: B, usage: U) -> Self {
+        Transfers { policy, backend, usage }
+    }
+
+    pub fn policy(&self) -> &TransferPolicy {
+        &self.policy
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    // `TransferPolicy::quote` checked against what `user_id` already sent today
+    pub async fn quote(&self, currency: &Currency, amount: Decimal, user_id: i64) -> Result<Quote, TransferError> {
+        let quote = self.policy.quote(currency, amount)?;
+
+        if let Some(limit) = self.policy.daily_limits.get(currency) {
+            let remaining = self.remaining(currency, *limit, user_id, Utc::now().date_naive()).await?;
+            if amount > remaining {
+                return Err(TransferError::DailyLimit { limit: *limit, remaining });
+            }
+        }
+
+        Ok(quote)
+    }
+
+    // Quotes and executes the transfer with the policy's fee. The amount is reserved
+    // against the daily limit before the transfer and released if it isn't applied.
+    pub async fn transfer(
+        &self,
+        currency: &Currency,
+        amount: Decimal,
+        user_id: i64,
+        receiver_id: i64,
+        key: Option<&str>,
+    ) -> Result<(Quote, BalanceOutcome), TransferError> {
+        let quote = self.policy.quote(currency, amount)?;
+
+        let today = Utc::now().date_naive();
+        let mut reservation = None;
+        if let Some(limit) = self.policy.daily_limits.get(currency).copied() {
+            let reserved = self.usage
+                .reserve(user_id, currency, today, quote.amount, limit, key)
+                .await
+                .map_err(TransferError::Usage)?;
+            if reserved == Reservation::OverLimit {
+                let remaining = self.remaining(currency, limit, user_id, today).await?;
+                return Err(TransferError::DailyLimit { limit, remaining });
+            }
+            reservation = Some(reserved);
+        }
+
+        let result = self.backend
+            .transfer(currency, &quote.amount, user_id, receiver_id, &quote.fee, key)
+            .await;
+
+        // Rejected transfers and replays of an earlier one don't count. Errors keep the
+        // reservation, the transfer may have been applied before the connection failed.
+        // A retry holding an earlier reservation keeps it unless it is rejected now.
+        let release = match (reservation, &result) {
+            (Some(Reservation::Reserved), Ok(outcome)) => !matches!(outcome, BalanceOutcome::Applied { .. }),
+            (Some(Reservation::Held), Ok(outcome)) => !outcome.is_applied(),
+            _ => false,
+        };
+        if release && let Err(err) = self.usage.release(user_id, currency, today, quote.amount, key).await {
+            eprintln!("Failed to release transfer usage of {}: {}", user_id, err);
+        }
+
+        let outcome = result.map_err(TransferError::Balance)?;
+        Ok((quote, outcome))
+    }
+
+    async fn remaining(&self, currency: &Currency, limit: Decimal, user_id: i64, day: NaiveDate) -> Result<Decimal, TransferError> {
+        let sent = self.usage.sent(user_id, currency, day).await.map_err(TransferError::Usage)?;
+        Ok((limit - sent).max(Decimal::ZERO))
+    }
+}
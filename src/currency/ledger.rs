@@ -0,0 +1,575 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::{BalanceBackend, Currency};
+use crate::balance::{BalanceError, BalanceOutcome};
+
+// Counter-account of deposits and withdrawals, the only one allowed to go negative
+pub const SYSTEM_ACCOUNT: i64 = 0;
+// Collects transfer fees
+pub const FEE_ACCOUNT: i64 = -1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    Add,
+    Deposit,
+    Sub,
+    Transfer,
+    Fee,
+}
+
+impl EntryKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryKind::Add => "add",
+            EntryKind::Deposit => "deposit",
+            EntryKind::Sub => "sub",
+            EntryKind::Transfer => "transfer",
+            EntryKind::Fee => "fee",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "add" => Some(EntryKind::Add),
+            "deposit" => Some(EntryKind::Deposit),
+            "sub" => Some(EntryKind::Sub),
+            "transfer" => Some(EntryKind::Transfer),
+            "fee" => Some(EntryKind::Fee),
+            _ => None,
+        }
+    }
+}
+
+// One side of an entry, the postings of an entry sum to zero per currency.
+// `kind` tells the legs of an entry apart, e.g. the fee of a transfer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Posting {
+    pub user_id: i64,
+    pub currency: Currency,
+    pub amount: Decimal,
+    pub kind: EntryKind,
+}
+
+// Immutable record of one balance movement, under the caller's idempotency key.
+// A transfer with a fee is one entry with the fee postings included.
+#[derive(Debug, Clone, Serialize)]
+pub struct JournalEntry {
+    pub key: String,
+    pub kind: EntryKind,
+    pub postings: Vec<Posting>,
+    pub created_at: DateTime<Utc>,
+}
+
+// A user's side of an entry, newest first in `history`
+#[derive(Debug, Clone, Serialize)]
+pub struct LedgerLine {
+    pub key: String,
+    pub kind: EntryKind,
+    pub amount: Decimal,
+    pub created_at: DateTime<Utc>,
+}
+
+// The entry an operation writes, the owner's balance ends up in the outcome body
+struct Plan {
+    owner: i64,
+    kind: EntryKind,
+    postings: Vec<Posting>,
+}
+
+fn rejected(key: String, status: StatusCode, reason: &str) -> BalanceOutcome {
+    BalanceOutcome::Rejected { key, status, reason: reason.to_string() }
+}
+
+fn key_or_new(key: Option<&str>) -> String {
+    match key {
+        Some(key) => key.to_string(),
+        None => crate::uuid::new().simple().to_string(),
+    }
+}
+
+// The system account may go negative, letting a caller act as it would create money
+fn check_accounts(user_ids: &[i64]) -> Result<(), &'static str> {
+    if user_ids.iter().any(|id| *id == SYSTEM_ACCOUNT || *id == FEE_ACCOUNT) {
+        return Err("Reserved account");
+    }
+    Ok(())
+}
+
+fn plan_add(currency: &Currency, amount: &Decimal, user_id: i64, is_deposit: bool) -> Result<Plan, &'static str> {
+    let kind = if is_deposit { EntryKind::Deposit } else { EntryKind::Add };
+    plan_add_multiple(&HashMap::from([(*currency, *amount)]), user_id, kind)
+}
+
+fn plan_add_multiple(currencies: &HashMap<Currency, Decimal>, user_id: i64, kind: EntryKind) -> Result<Plan, &'static str> {
+    check_accounts(&[user_id])?;
+    if currencies.is_empty() {
+        return Err("No amounts to add");
+    }
+    if currencies.values().any(|amount| *amount <= Decimal::ZERO) {
+        return Err("Amount must be positive");
+    }
+
+    let postings = currencies
+        .iter()
+        .flat_map(|(currency, amount)| [
+            Posting { user_id: SYSTEM_ACCOUNT, currency: *currency, amount: -*amount, kind },
+            Posting { user_id, currency: *currency, amount: *amount, kind },
+        ])
+        .collect();
+
+    Ok(Plan { owner: user_id, kind, postings })
+}
+
+fn plan_sub(currency: &Currency, amount: &Decimal, user_id: i64) -> Result<Plan, &'static str> {
+    check_accounts(&[user_id])?;
+    if *amount <= Decimal::ZERO {
+        return Err("Amount must be positive");
+    }
+
+    let kind = EntryKind::Sub;
+    Ok(Plan {
+        owner: user_id,
+        kind,
+        postings: vec![
+            Posting { user_id, currency: *currency, amount: -*amount, kind },
+            Posting { user_id: SYSTEM_ACCOUNT, currency: *currency, amount: *amount, kind },
+        ],
+    })
+}
+
+// The sender pays `amount`, the receiver gets `amount - fee`
+fn plan_transfer(
+    currency: &Currency,
+    amount: &Decimal,
+    user_id: i64,
+    receiver_id: i64,
+    fee: &Decimal,
+) -> Result<Plan, &'static str> {
+    check_accounts(&[user_id, receiver_id])?;
+    if *amount <= Decimal::ZERO {
+        return Err("Amount must be positive");
+    }
+    if *fee < Decimal::ZERO || fee > amount {
+        return Err("Fee must be between zero and the amount");
+    }
+    if user_id == receiver_id {
+        return Err("Can't transfer to yourself");
+    }
+
+    let mut postings = vec![
+        Posting { user_id, currency: *currency, amount: -*amount, kind: EntryKind::Transfer },
+        Posting { user_id: receiver_id, currency: *currency, amount: *amount, kind: EntryKind::Transfer },
+    ];
+    if !fee.is_zero() {
+        postings.extend([
+            Posting { user_id: receiver_id, currency: *currency, amount: -*fee, kind: EntryKind::Fee },
+            Posting { user_id: FEE_ACCOUNT, currency: *currency, amount: *fee, kind: EntryKind::Fee },
+        ]);
+    }
+
+    Ok(Plan { owner: user_id, kind: EntryKind::Transfer, postings })
+}
+
+impl Plan {
+    // Net change per account and currency, ordered so concurrent transactions
+    // lock rows in the same order
+    fn changes(&self) -> BTreeMap<(i64, u16), (Currency, Decimal)> {
+        let mut changes = BTreeMap::new();
+        for posting in &self.postings {
+            let (_, change) = changes
+                .entry((posting.user_id, posting.currency.id()))
+                .or_insert((posting.currency, Decimal::ZERO));
+            *change += posting.amount;
+        }
+        changes
+    }
+
+}
+
+// The owner's new balance for single currency plans, a code -> balance JSON
+// object when several currencies were added at once
+fn outcome_body(balances: &BTreeMap<u16, (Currency, Decimal)>) -> String {
+    match balances.values().collect::<Vec<_>>().as_slice() {
+        [(_, balance)] => balance.to_string(),
+        balances => {
+            let balances: BTreeMap<&str, String> = balances
+                .iter()
+                .map(|(currency, balance)| (currency.code(), balance.to_string()))
+                .collect();
+            serde_json::to_string(&balances).unwrap_or_default()
+        }
+    }
+}
+
+#[derive(Default)]
+struct MemoryLedgerState {
+    balances: HashMap<(i64, Currency), Decimal>,
+    journal: Vec<JournalEntry>,
+    // key -> outcome body, for duplicates
+    applied: HashMap<String, String>,
+}
+
+// Deterministic in-process ledger, for tests and single-instance services
+#[derive(Default)]
+pub struct MemoryLedger {
+    state: Mutex<MemoryLedgerState>,
+}
+
+impl MemoryLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn balance(&self, user_id: i64, currency: &Currency) -> Decimal {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.balances.get(&(user_id, *currency)).copied().unwrap_or(Decimal::ZERO)
+    }
+
+    pub fn journal(&self) -> Vec<JournalEntry> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).journal.clone()
+    }
+
+    pub fn history(&self, user_id: i64, currency: &Currency, limit: usize) -> Vec<LedgerLine> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.journal
+            .iter()
+            .rev()
+            .flat_map(|entry| {
+                entry.postings
+                    .iter()
+                    .rev()
+                    .filter(|p| p.user_id == user_id && p.currency == *currency)
+                    .map(|p| LedgerLine {
+                        key: entry.key.clone(),
+                        kind: p.kind,
+                        amount: p.amount,
+                        created_at: entry.created_at,
+                    })
+            })
+            .take(limit)
+            .collect()
+    }
+
+    fn apply(&self, key: String, plan: Result<Plan, &str>) -> BalanceOutcome {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(body) = state.applied.get(&key) {
+            return BalanceOutcome::Duplicate { body: body.clone(), key };
+        }
+
+        let plan = match plan {
+            Ok(plan) => plan,
+            Err(reason) => return rejected(key, StatusCode::BAD_REQUEST, reason),
+        };
+
+        let mut balances = HashMap::new();
+        let mut owner_balances = BTreeMap::new();
+        for ((user_id, id), (currency, change)) in plan.changes() {
+            let balance = state.balances.get(&(user_id, currency)).copied().unwrap_or(Decimal::ZERO) + change;
+            if balance < Decimal::ZERO && user_id != SYSTEM_ACCOUNT {
+                return rejected(key, StatusCode::PAYMENT_REQUIRED, "Insufficient funds");
+            }
+            if user_id == plan.owner {
+                owner_balances.insert(id, (currency, balance));
+            }
+            balances.insert((user_id, currency), balance);
+        }
+
+        state.balances.extend(balances);
+        let body = outcome_body(&owner_balances);
+        state.journal.push(JournalEntry {
+            key: key.clone(),
+            kind: plan.kind,
+            postings: plan.postings,
+            created_at: Utc::now(),
+        });
+        state.applied.insert(key.clone(), body.clone());
+        BalanceOutcome::Applied { key, body }
+    }
+}
+
+// The outcome body is the acting user's new balance (the sender's for transfers)
+impl BalanceBackend for MemoryLedger {
+    async fn add_multiple(
+        &self,
+        currencies: &HashMap<Currency, Decimal>,
+        user_id: i64,
+        key: Option<&str>,
+    ) -> Result<BalanceOutcome, BalanceError> {
+        let plan = plan_add_multiple(currencies, user_id, EntryKind::Add);
+        Ok(self.apply(key_or_new(key), plan))
+    }
+
+    async fn add(
+        &self,
+        currency: &Currency,
+        amount: &Decimal,
+        user_id: i64,
+        is_deposit: bool,
+        key: Option<&str>,
+    ) -> Result<BalanceOutcome, BalanceError> {
+        let plan = plan_add(currency, amount, user_id, is_deposit);
+        Ok(self.apply(key_or_new(key), plan))
+    }
+
+    async fn sub(&self, currency: &Currency, amount: &Decimal, user_id: i64, key: Option<&str>) -> Result<BalanceOutcome, BalanceError> {
+        let plan = plan_sub(currency, amount, user_id);
+        Ok(self.apply(key_or_new(key), plan))
+    }
+
+    async fn transfer(
+        &self,
+        currency: &Currency,
+        amount: &Decimal,
+        user_id: i64,
+        receiver_id: i64,
+        fee: &Decimal,
+        key: Option<&str>,
+    ) -> Result<BalanceOutcome, BalanceError> {
+        let plan = plan_transfer(currency, amount, user_id, receiver_id, fee);
+        Ok(self.apply(key_or_new(key), plan))
+    }
+}
+
+// Balances live in `ledger_accounts` and are only changed together with the
+// append-only `ledger_entries`/`ledger_postings` rows, in one transaction
+#[cfg(feature = "db")]
+pub struct PgLedger {
+    pool: crate::db::Pool,
+}
+
+#[cfg(feature = "db")]
+impl PgLedger {
+    pub fn new(pool: crate::db::Pool) -> Self {
+        PgLedger { pool }
+    }
+
+    pub async fn create_table(&self) -> Result<(), crate::db::StdError> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS ledger_accounts (
+                user_id BIGINT NOT NULL,
+                currency SMALLINT NOT NULL,
+                balance NUMERIC NOT NULL DEFAULT 0,
+                PRIMARY KEY (user_id, currency)
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS ledger_entries (
+                key TEXT PRIMARY KEY,
+                kind TEXT NOT NULL,
+                result TEXT,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS ledger_postings (
+                id BIGSERIAL PRIMARY KEY,
+                entry_key TEXT NOT NULL REFERENCES ledger_entries(key),
+                user_id BIGINT NOT NULL,
+                currency SMALLINT NOT NULL,
+                amount NUMERIC NOT NULL,
+                kind TEXT
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Tables created before postings had their own kind use the entry's
+        sqlx::query("ALTER TABLE ledger_postings ADD COLUMN IF NOT EXISTS kind TEXT")
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS ledger_postings_user ON ledger_postings (user_id, currency, id)")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn balance(&self, user_id: i64, currency: &Currency) -> Result<Decimal, String> {
+        let balance = sqlx::query_scalar::<_, Decimal>("SELECT balance FROM ledger_accounts WHERE user_id = $1 AND currency = $2")
+            .bind(user_id)
+            .bind(currency)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(balance.unwrap_or(Decimal::ZERO))
+    }
+
+    pub async fn history(&self, user_id: i64, currency: &Currency, limit: i64) -> Result<Vec<LedgerLine>, String> {
+        let rows = sqlx::query_as::<_, (String, String, Decimal, DateTime<Utc>)>(
+            r#"
+            SELECT e.key, COALESCE(p.kind, e.kind), p.amount, e.created_at
+            FROM ledger_postings p
+            JOIN ledger_entries e ON e.key = p.entry_key
+            WHERE p.user_id = $1 AND p.currency = $2
+            ORDER BY p.id DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(currency)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        rows.into_iter()
+            .map(|(key, kind, amount, created_at)| {
+                let kind = EntryKind::parse(&kind).ok_or_else(|| format!("Unknown entry kind {}", kind))?;
+                Ok(LedgerLine { key, kind, amount, created_at })
+            })
+            .collect()
+    }
+
+    async fn apply(&self, key: String, plan: Result<Plan, &str>) -> Result<BalanceOutcome, BalanceError> {
+        let storage = |e: sqlx::Error| BalanceError::Storage { key: key.clone(), message: e.to_string() };
+
+        let plan = match plan {
+            Ok(plan) => plan,
+            Err(reason) => {
+                // A duplicate of an applied request wins over validation
+                return match self.applied(&key).await.map_err(storage)? {
+                    Some(body) => Ok(BalanceOutcome::Duplicate { key, body }),
+                    None => Ok(rejected(key, StatusCode::BAD_REQUEST, reason)),
+                };
+            }
+        };
+
+        let mut tx = self.pool.begin().await.map_err(storage)?;
+
+        // Blocks on a concurrent request with the same key until it commits or rolls back
+        let inserted = sqlx::query("INSERT INTO ledger_entries (key, kind) VALUES ($1, $2) ON CONFLICT (key) DO NOTHING")
+            .bind(&key)
+            .bind(plan.kind.as_str())
+            .execute(&mut *tx)
+            .await
+            .map_err(storage)?;
+
+        if inserted.rows_affected() == 0 {
+            tx.rollback().await.map_err(storage)?;
+            let body = self.applied(&key).await.map_err(storage)?.unwrap_or_default();
+            return Ok(BalanceOutcome::Duplicate { key, body });
+        }
+
+        let mut owner_balances = BTreeMap::new();
+        for ((user_id, id), (currency, change)) in plan.changes() {
+            let balance = sqlx::query_scalar::<_, Decimal>(
+                r#"
+                INSERT INTO ledger_accounts (user_id, currency, balance)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (user_id, currency) DO UPDATE SET balance = ledger_accounts.balance + EXCLUDED.balance
+                RETURNING balance
+                "#,
+            )
+            .bind(user_id)
+            .bind(currency)
+            .bind(change)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(storage)?;
+
+            if balance < Decimal::ZERO && user_id != SYSTEM_ACCOUNT {
+                tx.rollback().await.map_err(storage)?;
+                return Ok(rejected(key, StatusCode::PAYMENT_REQUIRED, "Insufficient funds"));
+            }
+            if user_id == plan.owner {
+                owner_balances.insert(id, (currency, balance));
+            }
+        }
+
+        for posting in &plan.postings {
+            sqlx::query("INSERT INTO ledger_postings (entry_key, user_id, currency, amount, kind) VALUES ($1, $2, $3, $4, $5)")
+                .bind(&key)
+                .bind(posting.user_id)
+                .bind(posting.currency)
+                .bind(posting.amount)
+                .bind(posting.kind.as_str())
+                .execute(&mut *tx)
+                .await
+                .map_err(storage)?;
+        }
+
+        let body = outcome_body(&owner_balances);
+        sqlx::query("UPDATE ledger_entries SET result = $2 WHERE key = $1")
+            .bind(&key)
+            .bind(&body)
+            .execute(&mut *tx)
+            .await
+            .map_err(storage)?;
+
+        tx.commit().await.map_err(storage)?;
+        Ok(BalanceOutcome::Applied { key, body })
+    }
+
+    async fn applied(&self, key: &str) -> Result<Option<String>, sqlx::Error> {
+        let result = sqlx::query_scalar::<_, Option<String>>("SELECT result FROM ledger_entries WHERE key = $1")
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(result.map(Option::unwrap_or_default))
+    }
+}
+
+#[cfg(feature = "db")]
+impl BalanceBackend for PgLedger {
+    async fn add_multiple(
+        &self,
+        currencies: &HashMap<Currency, Decimal>,
+        user_id: i64,
+        key: Option<&str>,
+    ) -> Result<BalanceOutcome, BalanceError> {
+        let plan = plan_add_multiple(currencies, user_id, EntryKind::Add);
+        self.apply(key_or_new(key), plan).await
+    }
+
+    async fn add(
+        &self,
+        currency: &Currency,
+        amount: &Decimal,
+        user_id: i64,
+        is_deposit: bool,
+        key: Option<&str>,
+    ) -> Result<BalanceOutcome, BalanceError> {
+        let plan = plan_add(currency, amount, user_id, is_deposit);
+        self.apply(key_or_new(key), plan).await
+    }
+
+    async fn sub(&self, currency: &Currency, amount: &Decimal, user_id: i64, key: Option<&str>) -> Result<BalanceOutcome, BalanceError> {
+        let plan = plan_sub(currency, amount, user_id);
+        self.apply(key_or_new(key), plan).await
+    }
+
+    async fn transfer(
+        &self,
+        currency: &Currency,
+        amount: &Decimal,
+        user_id: i64,
+        receiver_id: i64,
+        fee: &Decimal,
+        key: Option<&str>,
+    ) -> Result<BalanceOutcome, BalanceError> {
+        let plan = plan_transfer(currency, amount, user_id, receiver_id, fee);
+        self.apply(key_or_new(key), plan).await
+    }
+}
@@ -0,0 +1,230 @@
+use rust_decimal::{Decimal, prelude::ToPrimitive};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{Currency, RoundMode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoneyError {
+    CurrencyMismatch { left: Currency, right: Currency },
+    Overflow,
+    DivisionByZero,
+}
+
+impl std::fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MoneyError::CurrencyMismatch { left, right } => write!(f, "Can't mix {} and {}", left, right),
+            MoneyError::Overflow => write!(f, "Amount overflow"),
+            MoneyError::DivisionByZero => write!(f, "Division by zero"),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+// An amount tied to its currency. Arithmetic refuses to mix currencies and
+// amounts can be moved to and from integer minor units (nanotons, cents...).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Money {
+    amount: Decimal,
+    currency: Currency,
+}
+
+impl Money {
+    // Kept as given, see `round` for the canonical precision
+    pub fn new(amount: Decimal, currency: Currency) -> Self {
+        Money { amount, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Money { amount: Decimal::ZERO, currency }
+    }
+
+    // e.g. `Money::from_minor(1_500_000_000, Currency::TON)` is 1.5 TON
+    pub fn from_minor(units: i64, currency: Currency) -> Self {
+        Money {
+            amount: Decimal::new(units, currency.minor_decimals()),
+            currency,
+        }
+    }
+
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.amount.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.amount.is_sign_negative() && !self.amount.is_zero()
+    }
+
+    // Rounds to the currency's precision (STARS 0 dp, TON 9 dp, PRESTIGE/USDT 2 dp),
+    // TICKETS are only normalized
+    pub fn round(&self, mode: RoundMode) -> Self {
+        let amount = match self.currency.decimals() {
+            Some(decimals) => self.amount.round_dp_with_strategy(decimals, mode.strategy()),
+            None => self.amount.normalize(),
+        };
+        Money { amount, currency: self.currency }
+    }
+
+    // Integer amount of minor units, None if it doesn't fit in an i64
+    pub fn to_minor(&self, mode: RoundMode) -> Option<i64> {
+        let decimals = self.currency.minor_decimals();
+        let scaled = self.amount.checked_mul(Decimal::from(10i64.checked_pow(decimals)?))?;
+        scaled.round_dp_with_strategy(0, mode.strategy()).to_i64()
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), MoneyError> {
+        if self.currency != other.currency {
+            return Err(MoneyError::CurrencyMismatch { left: self.currency, right: other.currency });
+        }
+        Ok(())
+    }
+
+    pub fn checked_add(&self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        let amount = self.amount.checked_add(other.amount).ok_or(MoneyError::Overflow)?;
+        Ok(Money { amount, currency: self.currency })
+    }
+
+    pub fn checked_sub(&self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        let amount = self.amount.checked_sub(other.amount).ok_or(MoneyError::Overflow)?;
+        Ok(Money { amount, currency: self.currency })
+    }
+
+    pub fn checked_mul(&self, factor: Decimal) -> Result<Money, MoneyError> {
+        let amount = self.amount.checked_mul(factor).ok_or(MoneyError::Overflow)?;
+        Ok(Money { amount, currency: self.currency })
+    }
+
+    pub fn checked_div(&self, divisor: Decimal) -> Result<Money, MoneyError> {
+        if divisor.is_zero() {
+            return Err(MoneyError::DivisionByZero);
+        }
+        let amount = self.amount.checked_div(divisor).ok_or(MoneyError::Overflow)?;
+        Ok(Money { amount, currency: self.currency })
+    }
+}
+
+impl std::fmt::Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.amount, self.currency)
+    }
+}
+
+// {"amount": "1.50", "currency": "USDT"}, the amount as a string so it survives JSON numbers
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Repr<'a> {
+            #[serde(with = "rust_decimal::serde::str")]
+            amount: Decimal,
+            currency: &'a Currency,
+        }
+
+        Repr { amount: self.amount, currency: &self.currency }.serialize(serializer)
+    }
+}
+
+// Accepts both forms: {"amount": "1.5" or 1.5, "currency": ...} and {"minor": 150, "currency": ...}
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Repr {
+            currency: Currency,
+            #[serde(default)]
+            amount: Option<Decimal>,
+            #[serde(default)]
+            minor: Option<i64>,
+        }
+
+        let repr = Repr::deserialize(deserializer)?;
+        match (repr.amount, repr.minor) {
+            (Some(amount), None) => Ok(Money::new(amount, repr.currency)),
+            (None, Some(minor)) => Ok(Money::from_minor(minor, repr.currency)),
+            _ => Err(serde::de::Error::custom("expected exactly one of `amount` or `minor`")),
+        }
+    }
+}
+
+// Minor-unit form for fields: `#[serde(with = "currency::money::minor")]`
+// gives {"minor": 150, "currency": "USDT"}, rounding to the nearest unit
+pub mod minor {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{Currency, Money, RoundMode};
+
+    pub fn serialize<S: Serializer>(money: &Money, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Repr<'a> {
+            minor: i64,
+            currency: &'a Currency,
+        }
+
+        let minor = money
+            .to_minor(RoundMode::Nearest)
+            .ok_or_else(|| serde::ser::Error::custom("amount doesn't fit in minor units"))?;
+        Repr { minor, currency: &money.currency }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+        Money::deserialize(deserializer)
+    }
+}
+
+// Binds as NUMERIC; the currency goes in its own column
+#[cfg(feature = "db")]
+mod sql {
+    use rust_decimal::Decimal;
+    use sqlx::{
+        Encode, FromRow, Postgres, Row, Type,
+        encode::IsNull,
+        error::BoxDynError,
+        postgres::{PgArgumentBuffer, PgRow, PgTypeInfo},
+    };
+
+    use super::{Currency, Money};
+
+    impl Type<Postgres> for Money {
+        fn type_info() -> PgTypeInfo {
+            <Decimal as Type<Postgres>>::type_info()
+        }
+
+        fn compatible(ty: &PgTypeInfo) -> bool {
+            <Decimal as Type<Postgres>>::compatible(ty)
+        }
+    }
+
+    impl Encode<'_, Postgres> for Money {
+        fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+            <Decimal as Encode<Postgres>>::encode_by_ref(&self.amount, buf)
+        }
+    }
+
+    impl Money {
+        // Reads the amount and currency from the named columns, e.g. `price` and `price_currency`.
+        // With a fixed currency decode the NUMERIC as Decimal and use `Money::new`.
+        pub fn from_columns(row: &PgRow, amount: &str, currency: &str) -> Result<Self, sqlx::Error> {
+            let amount: Decimal = row.try_get(amount)?;
+            let currency: Currency = row.try_get(currency)?;
+            Ok(Money::new(amount, currency))
+        }
+    }
+
+    // Only for rows whose columns are named exactly `amount` (NUMERIC) and `currency`
+    // (SMALLINT), use `Money::from_columns` for any other names
+    impl FromRow<'_, PgRow> for Money {
+        fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
+            Money::from_columns(row, "amount", "currency")
+        }
+    }
+
+}
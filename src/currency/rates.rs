@@ -0,0 +1,286 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+
+use super::{Currency, get_course};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateError {
+    Unknown(Currency),
+    // The last known rates are older than allowed
+    Stale { age_secs: u64 },
+    Source(String),
+}
+
+impl std::fmt::Display for RateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RateError::Unknown(currency) => write!(f, "No rate for {}", currency),
+            RateError::Stale { age_secs } => write!(f, "Rates are stale ({}s old)", age_secs),
+            RateError::Source(message) => write!(f, "Rate source failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for RateError {}
+
+// USD price of one unit of a currency
+pub trait RateProvider: Send + Sync {
+    fn rate(&self, currency: &Currency) -> impl Future<Output = Result<Decimal, RateError>> + Send;
+
+    // Rate in effect at `at`, providers without history answer with the current rate
+    fn rate_at(&self, currency: &Currency, _at: DateTime<Utc>) -> impl Future<Output = Result<Decimal, RateError>> + Send {
+        self.rate(currency)
+    }
+}
+
+// Fixed table, starting from the registry's default courses
+#[derive(Debug, Clone)]
+pub struct StaticRates {
+    rates: HashMap<Currency, Decimal>,
+}
+
+impl StaticRates {
+    pub fn new(ton_usd: Decimal) -> Self {
+        StaticRates {
+            rates: Currency::ALL.iter().map(|c| (*c, get_course(c, ton_usd))).collect(),
+        }
+    }
+
+    pub fn set(mut self, currency: Currency, rate: Decimal) -> Self {
+        self.rates.insert(currency, rate);
+        self
+    }
+
+    fn get(&self, currency: &Currency) -> Option<Decimal> {
+        self.rates.get(currency).copied()
+    }
+}
+
+impl RateProvider for StaticRates {
+    async fn rate(&self, currency: &Currency) -> Result<Decimal, RateError> {
+        self.get(currency).ok_or(RateError::Unknown(*currency))
+    }
+}
+
+// Rates with effective-from timestamps so past amounts convert at the rate of their time
+#[cfg(feature = "db")]
+pub struct PgRates {
+    pool: crate::db::Pool,
+}
+
+#[cfg(feature = "db")]
+impl PgRates {
+    pub fn new(pool: crate::db::Pool) -> Self {
+        PgRates { pool }
+    }
+
+    pub async fn create_table(&self) -> Result<(), crate::db::StdError> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS currency_rates (
+                currency SMALLINT NOT NULL,
+                rate NUMERIC NOT NULL,
+                effective_from TIMESTAMPTZ NOT NULL DEFAULT now(),
+                PRIMARY KEY (currency, effective_from)
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_rate(&self, currency: &Currency, rate: Decimal, effective_from: DateTime<Utc>) -> Result<(), crate::db::StdError> {
+        sqlx::query(
+            r#"
+            INSERT INTO currency_rates (currency, rate, effective_from)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (currency, effective_from) DO UPDATE SET rate = EXCLUDED.rate
+            "#,
+        )
+        .bind(currency)
+        .bind(rate)
+        .bind(effective_from)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "db")]
+impl RateProvider for PgRates {
+    async fn rate(&self, currency: &Currency) -> Result<Decimal, RateError> {
+        self.rate_at(currency, Utc::now()).await
+    }
+
+    async fn rate_at(&self, currency: &Currency, at: DateTime<Utc>) -> Result<Decimal, RateError> {
+        sqlx::query_scalar::<_, Decimal>(
+            r#"
+            SELECT rate FROM currency_rates
+            WHERE currency = $1 AND effective_from <= $2
+            ORDER BY effective_from DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(currency)
+        .bind(at)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| RateError::Source(e.to_string()))?
+        .ok_or(RateError::Unknown(*currency))
+        .and_then(|rate| positive_rate(currency, rate))
+    }
+}
+
+// Conversions divide by rates, a zero or negative one is a broken source
+fn positive_rate(currency: &Currency, rate: Decimal) -> Result<Decimal, RateError> {
+    if rate <= Decimal::ZERO {
+        return Err(RateError::Source(format!("Invalid rate {} for {}", rate, currency)));
+    }
+    Ok(rate)
+}
+
+struct CachedRates {
+    rates: HashMap<Currency, Decimal>,
+    fetched_at: Instant,
+}
+
+// Fetches {"TON": 5.1, "STARS": 0.015, ...} (USD per unit) from `url`, refreshing after
+// `refresh_after`. When the source is down the last rates are used until they are
+// older than `max_staleness`. Currencies missing from the response use `fallback`.
+pub struct HttpRates {
+    client: reqwest::Client,
+    url: String,
+    refresh_after: Duration,
+    max_staleness: Duration,
+    fallback: Option<StaticRates>,
+    cache: Mutex<Option<CachedRates>>,
+    // Held while fetching so only one caller refreshes, with the time and error
+    // of the last failed attempt for the callers that waited on it
+    refresh: tokio::sync::Mutex<Option<(Instant, String)>>,
+}
+
+impl HttpRates {
+    pub fn new(client: reqwest::Client, url: impl Into<String>) -> Self {
+        HttpRates {
+            client,
+            url: url.into(),
+            refresh_after: Duration::from_secs(60),
+            max_staleness: Duration::from_secs(15 * 60),
+            fallback: None,
+            cache: Mutex::new(None),
+            refresh: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    pub fn refresh_after(mut self, refresh_after: Duration) -> Self {
+        self.refresh_after = refresh_after;
+        self
+    }
+
+    pub fn max_staleness(mut self, max_staleness: Duration) -> Self {
+        self.max_staleness = max_staleness;
+        self
+    }
+
+    pub fn fallback(mut self, fallback: StaticRates) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    fn cached(&self, currency: &Currency, max_age: Duration) -> Option<Result<Decimal, RateError>> {
+        let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        let cached = cache.as_ref().filter(|cached| cached.fetched_at.elapsed() <= max_age)?;
+        Some(self.pick(&cached.rates, currency))
+    }
+
+    fn pick(&self, rates: &HashMap<Currency, Decimal>, currency: &Currency) -> Result<Decimal, RateError> {
+        rates
+            .get(currency)
+            .copied()
+            .or_else(|| self.fallback.as_ref()?.get(currency))
+            .ok_or(RateError::Unknown(*currency))
+    }
+
+    async fn fetch(&self) -> Result<HashMap<Currency, Decimal>, String> {
+        let response = self.client
+            .get(&self.url)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if !response.status().is_success() {
+            return Err(format!("Rate source returned {}", response.status()));
+        }
+
+        // Codes this version doesn't know and invalid rates are skipped instead of
+        // failing the whole set
+        let rates = response
+            .json::<HashMap<String, Decimal>>()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rates
+            .into_iter()
+            .filter_map(|(code, rate)| Some((Currency::from_code(&code)?, rate)))
+            .filter(|(currency, rate)| {
+                let valid = *rate > Decimal::ZERO;
+                if !valid {
+                    eprintln!("Skipping invalid rate {} for {} from {}", rate, currency, self.url);
+                }
+                valid
+            })
+            .collect())
+    }
+}
+
+impl RateProvider for HttpRates {
+    async fn rate(&self, currency: &Currency) -> Result<Decimal, RateError> {
+        if let Some(rate) = self.cached(currency, self.refresh_after) {
+            return rate;
+        }
+
+        // Callers that queued behind a refresh use its result instead of fetching again
+        let requested_at = Instant::now();
+        let mut refresh = self.refresh.lock().await;
+        if let Some(rate) = self.cached(currency, self.refresh_after) {
+            return rate;
+        }
+
+        let fetched = match refresh.as_ref() {
+            Some((failed_at, err)) if *failed_at >= requested_at => Err(err.clone()),
+            _ => {
+                let fetched = self.fetch().await;
+                *refresh = fetched.as_ref().err().map(|err| (Instant::now(), err.clone()));
+                fetched
+            }
+        };
+
+        match fetched {
+            Ok(rates) => {
+                let rate = self.pick(&rates, currency);
+                let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+                *cache = Some(CachedRates { rates, fetched_at: Instant::now() });
+                rate
+            }
+            Err(err) => {
+                eprintln!("Failed to refresh rates from {}: {}", self.url, err);
+                if let Some(rate) = self.cached(currency, self.max_staleness) {
+                    return rate;
+                }
+
+                let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+                match cache.as_ref() {
+                    Some(cached) => Err(RateError::Stale { age_secs: cached.fetched_at.elapsed().as_secs() }),
+                    None => Err(RateError::Source(err)),
+                }
+            }
+        }
+    }
+}
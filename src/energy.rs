@@ -1,5 +1,5 @@
 use std::sync::OnceLock;
-use crate::env;
+use crate::{balance::BalanceClient, env};
 
 static PROJECT: OnceLock<String> = OnceLock::new();
 
@@ -14,6 +14,82 @@ pub enum EnergyOutcome {
     LimitReached(serde_json::Value),   // state with *_resets_at for the error UI
 }
 
+impl BalanceClient {
+    // The client's project, falling back to PROJECT_NAME
+    fn energy_project(&self) -> &str {
+        self.project_name().unwrap_or_else(|| project_name())
+    }
+
+    pub async fn spend_energy(
+        &self,
+        user_id: i64,
+        amount: i32,
+        action: &str,
+        ref_id: &str,
+    ) -> Result<EnergyOutcome, String> {
+        let resp = self
+            .post("energy/spend")
+            .json(&serde_json::json!({
+                "user_id": user_id, "amount": amount,
+                "project": self.energy_project(), "action": action, "ref_id": ref_id,
+            }))
+            .send().await.map_err(|e| format!("energy service unreachable: {e}"))?;
+
+        let status = resp.status();
+        let body: serde_json::Value = resp.json().await.unwrap_or_default();
+        match status {
+            reqwest::StatusCode::OK => Ok(EnergyOutcome::Spent(body)),
+            reqwest::StatusCode::CONFLICT => Err(body.to_string()),
+            s => Err(format!("energy spend failed ({s}): {body}")),
+        }
+    }
+
+    pub async fn grant_energy(
+        &self,
+        user_id: i64,
+        amount: i32,
+        action: &str,
+        ref_id: &str,
+    ) -> Result<EnergyOutcome, String> {
+        let resp = self
+            .post("energy/grant")
+            .json(&serde_json::json!({
+                "user_id": user_id, "amount": amount,
+                "project": self.energy_project(), "action": action, "ref_id": ref_id,
+            }))
+            .send().await.map_err(|e| format!("energy service unreachable: {e}"))?;
+
+        let status = resp.status();
+        let body: serde_json::Value = resp.json().await.unwrap_or_default();
+        match status {
+            reqwest::StatusCode::OK => Ok(EnergyOutcome::Grant(body)),
+            reqwest::StatusCode::CONFLICT => Ok(EnergyOutcome::LimitReached(body)),
+            s => Err(format!("energy grant failed ({s}): {body}")),
+        }
+    }
+
+    pub async fn refund_energy(&self, ref_id: &str) -> Result<EnergyOutcome, String> {
+        let resp = self
+            .post("energy/refund")
+            .json(&serde_json::json!({
+                "project": self.energy_project(),
+                "ref_id": ref_id,
+            }))
+            .send().await.map_err(|e| format!("energy service unreachable: {e}"))?;
+
+        let status = resp.status();
+        let body: serde_json::Value = resp.json().await.unwrap_or_default();
+        match status {
+            reqwest::StatusCode::OK => Ok(EnergyOutcome::Refund(body)),
+            // reqwest::StatusCode::CONFLICT => Err(EnergyOutcome::LimitReached(body)),
+            s => Err(format!("energy refund failed ({s}): {body}")),
+        }
+    }
+}
+
+// The free functions below go through a `BalanceClient` at BALANCE_SERVICE_URL
+// (production by default), prefer holding a `BalanceClient` instead
+
 pub async fn spend(
     user_id: i64,
     amount: i32,
@@ -23,22 +99,9 @@ pub async fn spend(
     client: &reqwest::Client,
     internal_secret: &str,
 ) -> Result<EnergyOutcome, String> {
-    let resp = client
-        .post("https://prestige.up.railway.app/energy/spend")
-        .header("X-Internal-Secret", internal_secret)
-        .json(&serde_json::json!({
-            "user_id": user_id, "amount": amount,
-            "project": project_name(), "action": action, "ref_id": ref_id,
-        }))
-        .send().await.map_err(|e| format!("energy service unreachable: {e}"))?;
-
-    let status = resp.status();
-    let body: serde_json::Value = resp.json().await.unwrap_or_default();
-    match status {
-        reqwest::StatusCode::OK => Ok(EnergyOutcome::Spent(body)),
-        reqwest::StatusCode::CONFLICT => Err(body.to_string()),
-        s => Err(format!("energy spend failed ({s}): {body}")),
-    }
+    BalanceClient::with_secret(client, internal_secret)
+        .spend_energy(user_id, amount, action, ref_id)
+        .await
 }
 
 pub async fn grant(
@@ -50,22 +113,9 @@ pub async fn grant(
     client: &reqwest::Client,
     internal_secret: &str,
 ) -> Result<EnergyOutcome, String> {
-    let resp = client
-        .post("https://prestige.up.railway.app/energy/grant")
-        .header("X-Internal-Secret", internal_secret)
-        .json(&serde_json::json!({
-            "user_id": user_id, "amount": amount,
-            "project": project_name(), "action": action, "ref_id": ref_id,
-        }))
-        .send().await.map_err(|e| format!("energy service unreachable: {e}"))?;
-
-    let status = resp.status();
-    let body: serde_json::Value = resp.json().await.unwrap_or_default();
-    match status {
-        reqwest::StatusCode::OK => Ok(EnergyOutcome::Grant(body)),
-        reqwest::StatusCode::CONFLICT => Ok(EnergyOutcome::LimitReached(body)),
-        s => Err(format!("energy grant failed ({s}): {body}")),
-    }
+    BalanceClient::with_secret(client, internal_secret)
+        .grant_energy(user_id, amount, action, ref_id)
+        .await
 }
 
 pub async fn refund(
@@ -74,20 +124,7 @@ pub async fn refund(
     client: &reqwest::Client,
     internal_secret: &str,
 ) -> Result<EnergyOutcome, String> {
-    let resp = client
-        .post("https://prestige.up.railway.app/energy/refund")
-        .header("X-Internal-Secret", internal_secret)
-        .json(&serde_json::json!({
-            "project": project_name(),
-            "ref_id": ref_id,
-        }))
-        .send().await.map_err(|e| format!("energy service unreachable: {e}"))?;
-
-    let status = resp.status();
-    let body: serde_json::Value = resp.json().await.unwrap_or_default();
-    match status {
-        reqwest::StatusCode::OK => Ok(EnergyOutcome::Refund(body)),
-        // reqwest::StatusCode::CONFLICT => Err(EnergyOutcome::LimitReached(body)),
-        s => Err(format!("energy refund failed ({s}): {body}")),
-    }
-}
\ No newline at end of file
+    BalanceClient::with_secret(client, internal_secret)
+        .refund_energy(ref_id)
+        .await
+}
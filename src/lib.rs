@@ -25,6 +25,9 @@ pub mod db;
 #[cfg(feature = "telegram")]
 pub mod telegram;
 
+#[cfg(any(feature = "currency", feature = "energy"))]
+pub mod balance;
+
 #[cfg(feature = "currency")]
 pub mod currency;
 
@@ -34,6 +37,9 @@ pub mod energy;
 #[cfg(feature = "auth")]
 pub mod auth;
 
+#[cfg(feature = "auth")]
+pub mod rate_limit;
+
 #[cfg(feature = "tasker")]
 pub mod tasker;
 
@@ -0,0 +1,408 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{HeaderMap, HeaderValue, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use tower::{Layer, Service};
+
+use crate::auth;
+
+// Token bucket: holds up to `capacity` requests, refilled at `refill_per_sec`
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    pub capacity: u32,
+    pub refill_per_sec: f64,
+}
+
+impl Budget {
+    pub fn new(capacity: u32, per: Duration) -> Self {
+        Budget {
+            capacity,
+            refill_per_sec: capacity as f64 / per.as_secs_f64().max(0.001),
+        }
+    }
+
+    pub fn per_second(capacity: u32) -> Self {
+        Self::new(capacity, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(capacity: u32) -> Self {
+        Self::new(capacity, Duration::from_secs(60))
+    }
+
+    pub fn per_hour(capacity: u32) -> Self {
+        Self::new(capacity, Duration::from_secs(60 * 60))
+    }
+
+    // Outcome of a bucket that holds `tokens` after the refill
+    fn decide(&self, tokens: f64, allowed: bool) -> Decision {
+        let missing = (self.capacity as f64 - tokens).max(0.0);
+        Decision {
+            allowed,
+            limit: self.capacity,
+            remaining: tokens.max(0.0).floor() as u32,
+            retry_after_secs: if allowed { 0 } else { ((1.0 - tokens) / self.refill_per_sec).ceil().max(1.0) as u64 },
+            reset_secs: (missing / self.refill_per_sec).ceil() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    pub retry_after_secs: u64,
+    pub reset_secs: u64, // until the bucket is full again
+}
+
+pub trait RateLimitStore: Send + Sync + 'static {
+    // Takes one token from the bucket at `key`
+    fn take(&self, key: &str, budget: &Budget) -> impl Future<Output = Decision> + Send;
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    full_at: Instant,
+}
+
+#[derive(Default)]
+struct Buckets {
+    map: HashMap<String, Bucket>,
+    // Size the map has to grow to before the next sweep
+    sweep_at: usize,
+}
+
+// Per-process buckets, fine for a single instance
+#[derive(Default)]
+pub struct MemoryStore {
+    buckets: Mutex<Buckets>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+const MEMORY_STORE_SWEEP_AT: usize = 10_000;
+
+impl RateLimitStore for MemoryStore {
+    async fn take(&self, key: &str, budget: &Budget) -> Decision {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Full buckets behave exactly like missing ones, drop them. The next sweep
+        // waits until the map doubles, so sweeping stays O(1) per request.
+        if buckets.map.len() >= buckets.sweep_at.max(MEMORY_STORE_SWEEP_AT) {
+            buckets.map.retain(|_, bucket| bucket.full_at > now);
+            buckets.sweep_at = buckets.map.len() * 2;
+        }
+
+        let bucket = buckets.map.entry(key.to_string()).or_insert(Bucket {
+            tokens: budget.capacity as f64,
+            updated: now,
+            full_at: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        let mut tokens = (bucket.tokens + elapsed * budget.refill_per_sec).min(budget.capacity as f64);
+
+        let allowed = tokens >= 1.0;
+        if allowed {
+            tokens -= 1.0;
+        }
+
+        let missing = budget.capacity as f64 - tokens;
+        bucket.tokens = tokens;
+        bucket.updated = now;
+        bucket.full_at = now + Duration::from_secs_f64(missing / budget.refill_per_sec);
+
+        budget.decide(tokens, allowed)
+    }
+}
+
+// Buckets shared by every instance through Postgres
+#[cfg(feature = "db")]
+pub struct PgStore {
+    pool: crate::db::Pool,
+}
+
+#[cfg(feature = "db")]
+impl PgStore {
+    pub fn new(pool: crate::db::Pool) -> Self {
+        PgStore { pool }
+    }
+
+    pub async fn create_table(&self) -> Result<(), crate::db::StdError> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS rate_limits (
+                key TEXT PRIMARY KEY,
+                tokens DOUBLE PRECISION NOT NULL,
+                allowed BOOLEAN NOT NULL,
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Removes buckets that have been idle for longer than `idle`
+    pub async fn sweep(&self, idle: Duration) -> Result<u64, crate::db::StdError> {
+        let result = sqlx::query("DELETE FROM rate_limits WHERE updated_at < now() - make_interval(secs => $1)")
+            .bind(idle.as_secs_f64())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(feature = "db")]
+impl RateLimitStore for PgStore {
+    async fn take(&self, key: &str, budget: &Budget) -> Decision {
+        // Refill and take in one statement so concurrent instances can't race,
+        // every SET expression sees the old row
+        let result = sqlx::query_as::<_, (f64, bool)>(
+            r#"
+            INSERT INTO rate_limits (key, tokens, allowed, updated_at)
+            VALUES ($1, $2 - 1, TRUE, now())
+            ON CONFLICT (key) DO UPDATE SET
+                tokens = CASE
+                    WHEN LEAST($2, rate_limits.tokens + EXTRACT(EPOCH FROM (now() - rate_limits.updated_at)) * $3) >= 1
+                    THEN LEAST($2, rate_limits.tokens + EXTRACT(EPOCH FROM (now() - rate_limits.updated_at)) * $3) - 1
+                    ELSE LEAST($2, rate_limits.tokens + EXTRACT(EPOCH FROM (now() - rate_limits.updated_at)) * $3)
+                END,
+                allowed = LEAST($2, rate_limits.tokens + EXTRACT(EPOCH FROM (now() - rate_limits.updated_at)) * $3) >= 1,
+                updated_at = now()
+            RETURNING tokens, allowed
+            "#,
+        )
+        .bind(key)
+        .bind(budget.capacity as f64)
+        .bind(budget.refill_per_sec)
+        .fetch_one(&self.pool)
+        .await;
+
+        match result {
+            Ok((tokens, allowed)) => budget.decide(tokens, allowed),
+            Err(err) => {
+                // Fail open, a broken limiter shouldn't take the API down
+                eprintln!("Rate limit store error: {:?}", err);
+                budget.decide(budget.capacity as f64, true)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyBy {
+    User, // AuthenticatedUser id, client IP for anonymous requests
+    Ip,
+}
+
+struct Config<St> {
+    store: Arc<St>,
+    key_by: KeyBy,
+    trusted_proxies: usize,
+    default: Option<Budget>,
+    routes: Vec<(String, Budget)>,
+}
+
+impl<St> Clone for Config<St> {
+    fn clone(&self) -> Self {
+        Config {
+            store: self.store.clone(),
+            key_by: self.key_by,
+            trusted_proxies: self.trusted_proxies,
+            default: self.default,
+            routes: self.routes.clone(),
+        }
+    }
+}
+
+impl<St> Config<St> {
+    // Longest matching route prefix wins
+    fn budget(&self, path: &str) -> Option<(&str, Budget)> {
+        self.routes
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(prefix, budget)| (prefix.as_str(), *budget))
+            .or_else(|| self.default.map(|budget| ("*", budget)))
+    }
+}
+
+// Layers built from clones share the store and so the buckets
+pub struct RateLimitLayer<St> {
+    config: Config<St>,
+}
+
+impl<St> Clone for RateLimitLayer<St> {
+    fn clone(&self) -> Self {
+        RateLimitLayer { config: self.config.clone() }
+    }
+}
+
+impl<St: RateLimitStore> RateLimitLayer<St> {
+    pub fn new(store: St, key_by: KeyBy) -> Self {
+        RateLimitLayer {
+            config: Config {
+                store: Arc::new(store),
+                key_by,
+                trusted_proxies: 0,
+                default: None,
+                routes: Vec::new(),
+            },
+        }
+    }
+
+    // Budget for routes without their own, unlimited if not set
+    pub fn default_budget(mut self, budget: Budget) -> Self {
+        self.config.default = Some(budget);
+        self
+    }
+
+    // Budget for every path starting with `prefix`
+    pub fn route(mut self, prefix: &str, budget: Budget) -> Self {
+        self.config.routes.push((prefix.to_string(), budget));
+        self
+    }
+
+    // Number of reverse proxies in front of the app that append to X-Forwarded-For,
+    // the header is ignored when 0 (the default)
+    pub fn trusted_proxies(mut self, count: usize) -> Self {
+        self.config.trusted_proxies = count;
+        self
+    }
+}
+
+impl<S, St> Layer<S> for RateLimitLayer<St> {
+    type Service = RateLimit<S, St>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit { inner, config: Arc::new(self.config.clone()) }
+    }
+}
+
+pub struct RateLimit<S, St> {
+    inner: S,
+    config: Arc<Config<St>>,
+}
+
+impl<S: Clone, St> Clone for RateLimit<S, St> {
+    fn clone(&self) -> Self {
+        RateLimit { inner: self.inner.clone(), config: self.config.clone() }
+    }
+}
+
+impl<S, St, B> Service<Request<B>> for RateLimit<S, St>
+where
+    S: Service<Request<B>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    St: RateLimitStore,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        // The clone isn't necessarily ready, keep the one poll_ready was called on
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let Some((route, budget)) = config.budget(request.uri().path()) else {
+                return inner.call(request).await;
+            };
+
+            let key = format!("{}:{}", route, client_key(&request, &config));
+            let decision = config.store.take(&key, &budget).await;
+
+            let mut response = if decision.allowed {
+                inner.call(request).await?
+            } else {
+                (StatusCode::TOO_MANY_REQUESTS, "Too many requests").into_response()
+            };
+
+            set_headers(response.headers_mut(), &decision);
+            Ok(response)
+        })
+    }
+}
+
+fn client_key<B, St>(request: &Request<B>, config: &Config<St>) -> String {
+    if config.key_by == KeyBy::User {
+        let user_id = auth::request_token(request.headers())
+            .and_then(auth::decode_token)
+            .map(|claims| claims.sub);
+        if let Some(user_id) = user_id {
+            return format!("user:{}", user_id);
+        }
+    }
+
+    let ip = client_ip(request, config.trusted_proxies);
+    format!("ip:{}", ip.unwrap_or_else(|| "unknown".to_string()))
+}
+
+// The socket address, or behind `trusted_proxies` proxies the X-Forwarded-For hop
+// the outermost of them appended. Hops left of it are sent by the client and can
+// be spoofed.
+pub fn client_ip<B>(request: &Request<B>, trusted_proxies: usize) -> Option<String> {
+    let socket = || {
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string())
+    };
+    if trusted_proxies == 0 {
+        return socket();
+    }
+
+    // Every X-Forwarded-For header counts, in order
+    let hops: Vec<&str> = request
+        .headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(str::trim)
+        .collect();
+
+    // Fewer hops means the request didn't come through every proxy
+    hops.len()
+        .checked_sub(trusted_proxies)
+        .map(|index| hops[index])
+        .filter(|ip| !ip.is_empty())
+        .map(str::to_string)
+        .or_else(socket)
+}
+
+fn set_headers(headers: &mut HeaderMap, decision: &Decision) {
+    headers.insert("x-ratelimit-limit", HeaderValue::from(decision.limit));
+    headers.insert("x-ratelimit-remaining", HeaderValue::from(decision.remaining));
+    headers.insert("x-ratelimit-reset", HeaderValue::from(decision.reset_secs));
+    if !decision.allowed {
+        headers.insert(axum::http::header::RETRY_AFTER, HeaderValue::from(decision.retry_after_secs));
+    }
+}
@@ -68,6 +68,41 @@ pub async fn new_action(
         // Ok(())
 }
 
+// Records the action only if the user has never done it, for one-time tasks.
+// Concurrent calls for the same user and action are serialized by an advisory
+// lock, so exactly one of them inserts. Returns whether this call inserted it.
+pub async fn record_action_once(
+    pool: &sqlx::PgPool,
+    action_type: &str,
+    user_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1 || ':' || $2::text, 0))")
+        .bind(action_type)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let inserted = sqlx::query(
+        r#"
+        INSERT INTO tasker_actions (action_type, user_id, created_at)
+        SELECT $1, $2, now()
+        WHERE NOT EXISTS (
+            SELECT 1 FROM tasker_actions WHERE action_type = $1 AND user_id = $2
+        )
+        "#,
+    )
+    .bind(action_type)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected() == 1;
+
+    tx.commit().await?;
+    Ok(inserted)
+}
+
 pub async fn check_weekly_task(pool: &sqlx::PgPool, action_type: &str, user_id: i64) -> i64 {
     let from_timestamp = get_current_monday_timestamp();
 
@@ -218,9 +253,28 @@ pub async fn check_monthly_task(pool: &sqlx::PgPool, action_type: &str, user_id:
 }
 
 pub async fn check_special_task(pool: &sqlx::PgPool, action_type: &str, user_id: i64) -> i64 {
-    // For Special/Social/Partner tasks, we usually check the Lifetime Sum
-    // We can use a range from Unix Epoch (0) to now.
-    let to_ts = Utc::now().timestamp();
-    
-    get_sum_in_range(pool, action_type, user_id, 0, to_ts).await
+    match try_check_special_task(pool, action_type, user_id).await {
+        Ok(count) => count,
+        Err(err) => {
+            println!("Database query error: {:?}", err);
+            0
+        }
+    }
+}
+
+// For Special/Social/Partner tasks, we usually check the Lifetime Sum. Counts every
+// action without an upper bound, so one recorded in the current second is included.
+pub async fn try_check_special_task(pool: &sqlx::PgPool, action_type: &str, user_id: i64) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(*)
+        FROM tasker_actions
+        WHERE action_type = $1
+            AND user_id = $2
+        "#,
+    )
+    .bind(action_type)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
 }
\ No newline at end of file
@@ -1,13 +1,45 @@
 // use urlencoding::decode;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use hmac::{Hmac, Mac};
 use reqwest::Client;
-use sha2::{Sha256};
+use ring::signature::{ED25519, UnparsedPublicKey};
+use sha2::{Digest, Sha256};
 
-use std::{eprintln, error::Error};
+use std::{collections::HashMap, eprintln, error::Error, pin::Pin, sync::Mutex};
 use serde::{Serialize, Deserialize};
 
 use crate::env;
 
+pub mod avatar;
+pub mod bot;
+pub mod broadcast;
+pub mod dispatch;
+pub mod format;
+pub mod inline;
+pub mod keyboard;
+pub mod membership;
+pub mod payments;
+pub mod polling;
+pub mod start;
+pub mod types;
+
+#[cfg(feature = "axum")]
+pub mod webhook;
+
+pub use avatar::{Avatar, ProfilePictures, fetch_avatar};
+pub use bot::{Bot, InputFile, TelegramError};
+pub use bot::{AnswerCallbackQuery, EditMessageText, SendDocument, SendMessage, SendPhoto};
+pub use broadcast::{Broadcast, Broadcaster, DeliveryStatus};
+pub use dispatch::{Command, Dispatcher, HandlerResult};
+pub use format::{Html, escape};
+pub use inline::{AnswerInlineQuery, InlineQueryResult, InlineQueryResultArticle, InlineQueryResultPhoto, InputMessageContent};
+pub use keyboard::{InlineKeyboardButton, InlineKeyboardMarkup, KeyboardButton, MenuButton, ReplyKeyboardMarkup, ReplyMarkup};
+pub use membership::{Membership, MembershipChecker};
+pub use payments::{Invoice, StarsPayments};
+pub use polling::Polling;
+pub use start::StartParam;
+pub use types::{ChatId, ParseMode, Update};
+
 // #[derive(Debug, Serialize, Deserialize)]
 // pub struct User {
 //     pub id: i64,
@@ -245,7 +277,7 @@ use crate::env;
 
 
 // GEMINI
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: i64,
     pub first_name: String,
@@ -263,51 +295,296 @@ pub struct User {
     pub photo_url: String,
 }
 
-pub fn validate_init_data(raw_init_data: &str, bot_token: &str) -> Result<bool, Box<dyn Error>> {
-    // 1. Parse query string into key-value pairs
-    let mut params: Vec<(String, String)> = Vec::new();
-    let mut provided_hash = String::new();
+// Chat the Mini App was opened from through an attachment menu
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebAppChat {
+    pub id: i64,
+    #[serde(rename = "type")]
+    pub kind: String, // "group", "supergroup" or "channel"
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub photo_url: Option<String>,
+}
 
-    for pair in raw_init_data.split('&') {
-        let mut split = pair.splitn(2, '=');
-        let key = split.next().ok_or("Invalid pair")?;
-        let value = split.next().ok_or("Invalid value")?;
-        
-        // URL decode the value only
-        let decoded_value = urlencoding::decode(value)?;
+// Everything Telegram passes to a Mini App launch (WebAppInitData)
+#[derive(Debug, Clone, Serialize)]
+pub struct InitData {
+    pub query_id: Option<String>, // for answerWebAppQuery
+    pub user: Option<User>,
+    pub receiver: Option<User>, // chat partner in private chats opened from the attachment menu
+    pub chat: Option<WebAppChat>,
+    pub chat_type: Option<String>, // "sender", "private", "group", "supergroup" or "channel"
+    pub chat_instance: Option<String>,
+    pub start_param: Option<String>,
+    pub can_send_after: Option<i64>, // seconds until answerWebAppQuery may be called
+    pub auth_date: i64,
+    pub hash: String,
+}
+
+impl InitData {
+    // Parses without validating, use `validate_and_parse_init_data` for untrusted input
+    pub fn parse(raw_init_data: &str) -> Result<Self, Box<dyn Error>> {
+        let (params, hash) = parse_init_data_pairs(raw_init_data, "hash")?;
+        Self::from_pairs(&params, hash)
+    }
 
-        if key == "hash" {
-            provided_hash = decoded_value.into_owned();
+    fn from_pairs(params: &[(String, String)], hash: String) -> Result<Self, Box<dyn Error>> {
+        let get = |key: &str| params.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+
+        Ok(InitData {
+            query_id: get("query_id").map(str::to_string),
+            user: parse_json(get("user"))?,
+            receiver: parse_json(get("receiver"))?,
+            chat: parse_json(get("chat"))?,
+            chat_type: get("chat_type").map(str::to_string),
+            chat_instance: get("chat_instance").map(str::to_string),
+            start_param: get("start_param").map(str::to_string),
+            can_send_after: get("can_send_after").and_then(|v| v.parse().ok()),
+            auth_date: get("auth_date").ok_or("Missing auth_date")?.parse()?,
+            hash,
+        })
+    }
+
+    // Decoded start_param of a startapp link
+    pub fn start(&self) -> Option<StartParam> {
+        StartParam::decode(self.start_param.as_deref()?)
+    }
+}
+
+fn parse_json<T: serde::de::DeserializeOwned>(value: Option<&str>) -> Result<Option<T>, Box<dyn Error>> {
+    match value {
+        Some(value) => Ok(Some(serde_json::from_str(value)?)),
+        None => Ok(None),
+    }
+}
+
+pub const MAX_INIT_DATA_AGE_SECS: i64 = 3600; // 1 hour is plenty for a mini-app launch
+pub const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+#[derive(Debug, Clone, Copy)]
+pub struct InitDataPolicy {
+    pub max_age_secs: i64,       // how old auth_date may be before initData is considered stale
+    pub max_clock_skew_secs: i64, // how far in the future auth_date may be (client/server clock drift)
+}
+
+impl Default for InitDataPolicy {
+    fn default() -> Self {
+        InitDataPolicy {
+            max_age_secs: MAX_INIT_DATA_AGE_SECS,
+            max_clock_skew_secs: MAX_CLOCK_SKEW_SECS,
         }
-        //  else if key == "signature" {
-        //     // SKIP the signature field for HMAC validation
-        //     continue;
-        // }
-         else {
-            params.push((key.to_string(), decoded_value.into_owned()));
+    }
+}
+
+pub type ReplayFuture<'a> = Pin<Box<dyn Future<Output = Result<bool, String>> + Send + 'a>>;
+
+// Remembers which initData payloads were already exchanged, so a captured
+// launch payload can't be replayed within its max age. Boxed so validators
+// can take any store as `&dyn ReplayStore`.
+pub trait ReplayStore: Send + Sync {
+    // Resolves to false if `key` was already claimed and hasn't expired yet.
+    fn claim<'a>(&'a self, key: &'a str, expires_at: i64) -> ReplayFuture<'a>;
+}
+
+// Size the seen map has to reach before expired keys are first swept
+const REPLAY_STORE_SWEEP_AT: usize = 10_000;
+
+#[derive(Default)]
+struct SeenKeys {
+    map: HashMap<String, i64>, // key -> expires_at (unix seconds)
+    // Size the map has to grow to before the next sweep
+    sweep_at: usize,
+}
+
+#[derive(Default)]
+pub struct MemoryReplayStore {
+    seen: Mutex<SeenKeys>,
+}
+
+impl MemoryReplayStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MemoryReplayStore {
+    fn claim_now(&self, key: &str, expires_at: i64) -> bool {
+        let now = chrono::Utc::now().timestamp();
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Drop expired entries so the map doesn't grow forever. The next sweep
+        // waits until the map doubles, so sweeping stays O(1) per login.
+        if seen.map.len() >= seen.sweep_at.max(REPLAY_STORE_SWEEP_AT) {
+            seen.map.retain(|_, exp| *exp > now);
+            seen.sweep_at = seen.map.len() * 2;
         }
+
+        // An expired claim that wasn't swept yet can be taken over
+        if seen.map.get(key).is_some_and(|exp| *exp > now) {
+            return false;
+        }
+        seen.map.insert(key.to_string(), expires_at);
+        true
     }
+}
 
-    if provided_hash.is_empty() {
-        return Err("No hash found".into());
+impl ReplayStore for MemoryReplayStore {
+    fn claim<'a>(&'a self, key: &'a str, expires_at: i64) -> ReplayFuture<'a> {
+        let claimed = self.claim_now(key, expires_at);
+        Box::pin(async move { Ok(claimed) })
     }
-    
-    const MAX_INIT_DATA_AGE_SECS: i64 = 3600; // 1 hour is plenty for a mini-app launch
+}
 
-    // let auth_date: i64 = params.iter()
-    //     .find(|(k, _)| k == "auth_date")
-    //     .and_then(|(_, v)| v.parse().ok())
-    //     .ok_or("Missing auth_date")?;
+// Shared between instances, expired keys can be reclaimed and are removed by `purge_expired`
+#[cfg(feature = "db")]
+pub struct PgReplayStore {
+    pool: crate::db::Pool,
+}
 
-    // let age = chrono::Utc::now().timestamp() - auth_date;
-    // if age > MAX_INIT_DATA_AGE_SECS || age < -300 {
-    //     eprintln!("auth_data is wrong");
-    //     return Ok(false); // stale (replayed) or clock-skewed init data
-    // }
+#[cfg(feature = "db")]
+impl PgReplayStore {
+    pub fn new(pool: crate::db::Pool) -> Self {
+        PgReplayStore { pool }
+    }
 
-    // let auth_date = params.iter().find(|(k, _)| k == "auth_date")...
-    // if current_time - auth_date > 86400 { return Ok(false); }
+    pub async fn create_table(&self) -> Result<(), crate::db::StdError> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS init_data_replays (
+                key TEXT PRIMARY KEY,
+                expires_at BIGINT NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn purge_expired(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM init_data_replays WHERE expires_at <= $1")
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
 
+#[cfg(feature = "db")]
+impl ReplayStore for PgReplayStore {
+    fn claim<'a>(&'a self, key: &'a str, expires_at: i64) -> ReplayFuture<'a> {
+        Box::pin(async move {
+            // Inserted, or taken over from an expired claim
+            let claimed = sqlx::query_scalar::<_, String>(
+                r#"
+                INSERT INTO init_data_replays (key, expires_at) VALUES ($1, $2)
+                ON CONFLICT (key) DO UPDATE SET expires_at = EXCLUDED.expires_at
+                WHERE init_data_replays.expires_at <= $3
+                RETURNING key
+                "#,
+            )
+            .bind(key)
+            .bind(expires_at)
+            .bind(chrono::Utc::now().timestamp())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+            Ok(claimed.is_some())
+        })
+    }
+}
+
+// Claims a verified payload in `replay`, keyed by its normalized proof
+async fn claim_once(
+    replay: Option<&dyn ReplayStore>,
+    key: &str,
+    auth_date: i64,
+    policy: &InitDataPolicy,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let Some(replay) = replay else {
+        return Ok(true);
+    };
+
+    // Each payload can be exchanged only once while it is still fresh
+    if !replay.claim(key, auth_date + policy.max_age_secs).await? {
+        eprintln!("initData replay detected");
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// Claims validated initData, after the caller is done checking its contents
+pub async fn claim_init_data(
+    init_data: &InitData,
+    policy: &InitDataPolicy,
+    replay: Option<&dyn ReplayStore>,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    // hex::decode accepted the hash in any case, so lowercase it is the canonical form
+    let key = format!("hash:{}", init_data.hash.to_ascii_lowercase());
+    claim_once(replay, &key, init_data.auth_date, policy).await
+}
+
+pub fn validate_init_data(raw_init_data: &str, bot_token: &str) -> Result<bool, Box<dyn Error>> {
+    Ok(validate_and_parse_init_data(raw_init_data, bot_token)?.is_some())
+}
+
+pub async fn validate_init_data_with(
+    raw_init_data: &str,
+    bot_token: &str,
+    policy: &InitDataPolicy,
+    replay: Option<&dyn ReplayStore>,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    Ok(validate_and_parse_init_data_with(raw_init_data, bot_token, policy, replay).await?.is_some())
+}
+
+// Validates initData and parses it in the same pass, None if it isn't valid
+pub fn validate_and_parse_init_data(raw_init_data: &str, bot_token: &str) -> Result<Option<InitData>, Box<dyn Error>> {
+    validate_and_parse_init_data_policy(raw_init_data, bot_token, &InitDataPolicy::default())
+}
+
+// Claims the payload in `replay` only once it is verified and parsed. Use
+// `replay: None` and `claim_init_data` to check the contents before claiming.
+pub async fn validate_and_parse_init_data_with(
+    raw_init_data: &str,
+    bot_token: &str,
+    policy: &InitDataPolicy,
+    replay: Option<&dyn ReplayStore>,
+) -> Result<Option<InitData>, Box<dyn Error + Send + Sync>> {
+    let init_data = validate_and_parse_init_data_policy(raw_init_data, bot_token, policy).map_err(|e| e.to_string())?;
+    let Some(init_data) = init_data else {
+        return Ok(None);
+    };
+
+    if !claim_init_data(&init_data, policy, replay).await? {
+        return Ok(None);
+    }
+    Ok(Some(init_data))
+}
+
+// `validate_and_parse_init_data` with a custom age policy, never claims a replay store
+pub fn validate_and_parse_init_data_policy(
+    raw_init_data: &str,
+    bot_token: &str,
+    policy: &InitDataPolicy,
+) -> Result<Option<InitData>, Box<dyn Error>> {
+    let (mut params, provided_hash) = parse_init_data_pairs(raw_init_data, "hash")?;
+
+    if !verify_init_data_hash(&mut params, &provided_hash, bot_token, policy)? {
+        return Ok(None);
+    }
+
+    InitData::from_pairs(&params, provided_hash).map(Some)
+}
+
+fn verify_init_data_hash(
+    params: &mut InitDataPairs,
+    provided_hash: &str,
+    bot_token: &str,
+    policy: &InitDataPolicy,
+) -> Result<bool, Box<dyn Error>> {
     // 2. Sort parameters alphabetically
     params.sort_by(|a, b| a.0.cmp(&b.0));
 
@@ -328,10 +605,221 @@ pub fn validate_init_data(raw_init_data: &str, bot_token: &str) -> Result<bool,
     hmac.update(data_check_string.as_bytes());
     
     // Convert provided hex hash to bytes for verification
-    let provided_hash_bytes = hex::decode(&provided_hash)?;
+    let provided_hash_bytes = hex::decode(provided_hash)?;
     
     // .verify_slice() performs a constant-time comparison
-    Ok(hmac.verify_slice(&provided_hash_bytes).is_ok())
+    if hmac.verify_slice(&provided_hash_bytes).is_err() {
+        return Ok(false);
+    }
+
+    // 6. Reject stale or clock-skewed init data
+    Ok(check_freshness(params, policy)?.is_some())
+}
+
+// Ed25519 keys Telegram publishes for third-party initData validation
+pub const TELEGRAM_PUBLIC_KEY: &str = "e7bf03a2fa4602af4580703d88dda5bb59f32ed8b02a56c187fe7d34caed242d";
+pub const TELEGRAM_TEST_PUBLIC_KEY: &str = "40055058a4ee38156a06562e52eece92a771bcd8346a8c4615cb7376eddf72ec";
+
+// Validates initData through its `signature` field, which only needs the bot id
+// and Telegram's public key, so services without the bot token can verify users.
+pub fn validate_init_data_signature(raw_init_data: &str, bot_id: i64) -> Result<bool, Box<dyn Error>> {
+    let verified = verify_init_data_signature(raw_init_data, bot_id, TELEGRAM_PUBLIC_KEY, &InitDataPolicy::default())?;
+    Ok(verified.is_some())
+}
+
+pub async fn validate_init_data_signature_with(
+    raw_init_data: &str,
+    bot_id: i64,
+    public_key_hex: &str,
+    policy: &InitDataPolicy,
+    replay: Option<&dyn ReplayStore>,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let verified = verify_init_data_signature(raw_init_data, bot_id, public_key_hex, policy).map_err(|e| e.to_string())?;
+    let Some((key, auth_date)) = verified else {
+        return Ok(false);
+    };
+
+    claim_once(replay, &key, auth_date, policy).await
+}
+
+// (replay key, auth_date) of a validly signed, fresh payload
+fn verify_init_data_signature(
+    raw_init_data: &str,
+    bot_id: i64,
+    public_key_hex: &str,
+    policy: &InitDataPolicy,
+) -> Result<Option<(String, i64)>, Box<dyn Error>> {
+    // 1. Parse query string, pulling out the signature
+    let (mut params, signature) = parse_init_data_pairs(raw_init_data, "signature")?;
+
+    // hash is signed with the bot token, it is not part of the signed data
+    params.retain(|(k, _)| k != "hash");
+
+    // 2. Sort parameters alphabetically
+    params.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // 3. Construct data_check_string, prefixed with "{bot_id}:WebAppData"
+    let data_check_string = std::iter::once(format!("{}:WebAppData", bot_id))
+        .chain(params.iter().map(|(k, v)| format!("{}={}", k, v)))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // 4. Signature is base64url without padding
+    let signature_bytes = URL_SAFE_NO_PAD.decode(signature.trim_end_matches('='))?;
+    let public_key = hex::decode(public_key_hex)?;
+
+    // 5. Verify with Telegram's public key
+    let key = UnparsedPublicKey::new(&ED25519, &public_key);
+    if key.verify(data_check_string.as_bytes(), &signature_bytes).is_err() {
+        return Ok(None);
+    }
+
+    // 6. Reject stale or clock-skewed init data. The replay key is the re-encoded
+    // signature, so padding or percent-encoding variants map to the same key.
+    let key = format!("sig:{}", URL_SAFE_NO_PAD.encode(&signature_bytes));
+    Ok(check_freshness(&params, policy)?.map(|auth_date| (key, auth_date)))
+}
+
+// Payload of the Telegram Login Widget (web login outside of Telegram)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginWidgetData {
+    pub id: i64,
+    pub first_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub photo_url: Option<String>,
+    pub auth_date: i64,
+    pub hash: String,
+}
+
+impl LoginWidgetData {
+    pub fn user(&self) -> User {
+        User {
+            id: self.id,
+            first_name: self.first_name.clone(),
+            last_name: self.last_name.clone().unwrap_or_default(),
+            username: self.username.clone().unwrap_or_default(),
+            language_code: String::new(),
+            is_premium: false,
+            allows_write_to_pm: false,
+            photo_url: self.photo_url.clone().unwrap_or_default(),
+        }
+    }
+}
+
+pub fn validate_login_widget(data: &LoginWidgetData, bot_token: &str) -> Result<bool, Box<dyn Error>> {
+    Ok(verify_login_widget(data, bot_token, &InitDataPolicy::default())?.is_some())
+}
+
+pub async fn validate_login_widget_with(
+    data: &LoginWidgetData,
+    bot_token: &str,
+    policy: &InitDataPolicy,
+    replay: Option<&dyn ReplayStore>,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let verified = verify_login_widget(data, bot_token, policy).map_err(|e| e.to_string())?;
+    let Some((key, auth_date)) = verified else {
+        return Ok(false);
+    };
+
+    claim_once(replay, &key, auth_date, policy).await
+}
+
+// (replay key, auth_date) of a valid, fresh widget login
+fn verify_login_widget(
+    data: &LoginWidgetData,
+    bot_token: &str,
+    policy: &InitDataPolicy,
+) -> Result<Option<(String, i64)>, Box<dyn Error>> {
+    // 1. Collect every received field except hash
+    let mut params: InitDataPairs = vec![
+        ("id".to_string(), data.id.to_string()),
+        ("first_name".to_string(), data.first_name.clone()),
+        ("auth_date".to_string(), data.auth_date.to_string()),
+    ];
+    for (key, value) in [
+        ("last_name", &data.last_name),
+        ("username", &data.username),
+        ("photo_url", &data.photo_url),
+    ] {
+        if let Some(value) = value {
+            params.push((key.to_string(), value.clone()));
+        }
+    }
+
+    // 2. Sort parameters alphabetically
+    params.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // 3. Construct data_check_string
+    let data_check_string = params
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // 4. Unlike Mini Apps, the widget secret key is SHA256(bot_token)
+    let secret_key = Sha256::digest(bot_token.as_bytes());
+
+    // 5. Compute Hash
+    let mut hmac = Hmac::<Sha256>::new_from_slice(&secret_key)?;
+    hmac.update(data_check_string.as_bytes());
+
+    let provided_hash_bytes = hex::decode(&data.hash)?;
+    if hmac.verify_slice(&provided_hash_bytes).is_err() {
+        return Ok(None);
+    }
+
+    // 6. Reject stale or clock-skewed login data, keyed by the hash in one case
+    let key = format!("widget:{}", hex::encode(&provided_hash_bytes));
+    Ok(check_freshness(&params, policy)?.map(|auth_date| (key, auth_date)))
+}
+
+type InitDataPairs = Vec<(String, String)>;
+
+// Splits raw initData into URL-decoded pairs, returning `proof_key`'s value separately
+fn parse_init_data_pairs(raw_init_data: &str, proof_key: &str) -> Result<(InitDataPairs, String), Box<dyn Error>> {
+    let mut params: InitDataPairs = Vec::new();
+    let mut proof = String::new();
+
+    for pair in raw_init_data.split('&') {
+        let mut split = pair.splitn(2, '=');
+        let key = split.next().ok_or("Invalid pair")?;
+        let value = split.next().ok_or("Invalid value")?;
+        
+        // URL decode the value only
+        let decoded_value = urlencoding::decode(value)?;
+
+        if key == proof_key {
+            proof = decoded_value.into_owned();
+        } else {
+            params.push((key.to_string(), decoded_value.into_owned()));
+        }
+    }
+
+    if proof.is_empty() {
+        return Err(format!("No {} found", proof_key).into());
+    }
+
+    Ok((params, proof))
+}
+
+// auth_date if it is within the policy's age and clock skew
+fn check_freshness(params: &[(String, String)], policy: &InitDataPolicy) -> Result<Option<i64>, Box<dyn Error>> {
+    let auth_date: i64 = params.iter()
+        .find(|(k, _)| k == "auth_date")
+        .and_then(|(_, v)| v.parse().ok())
+        .ok_or("Missing auth_date")?;
+
+    let age = chrono::Utc::now().timestamp() - auth_date;
+    if age > policy.max_age_secs || age < -policy.max_clock_skew_secs {
+        eprintln!("initData auth_date out of range (age {}s)", age);
+        return Ok(None);
+    }
+
+    Ok(Some(auth_date))
 }
 
 pub fn extract_user(init_data: &str) -> Option<User> {
@@ -345,18 +833,14 @@ pub fn extract_user(init_data: &str) -> Option<User> {
     serde_json::from_str::<User>(&decoded_json).ok()
 }
 
-#[derive(serde::Serialize)]
-struct TelegramMessage {
-    chat_id: String,
-
-    #[serde(skip_serializing_if = "Option::is_none")]
-    message_thread_id: Option<i32>,
-
-    text: String,
-    parse_mode: String,
+// start_param of a Mini App opened through a startapp link
+pub fn extract_start_param(init_data: &str) -> Option<String> {
+    let start_param = init_data
+        .split('&')
+        .find(|pair| pair.starts_with("start_param="))?
+        .strip_prefix("start_param=")?;
 
-    #[serde(skip_serializing_if = "Option::is_none")]
-    disable_web_page_preview: Option<bool>,
+    urlencoding::decode(start_param).ok().map(|param| param.into_owned())
 }
 
 pub async fn post(
@@ -367,37 +851,22 @@ pub async fn post(
 
     client: &Client,
 ) -> Result<(), reqwest::Error> {
-    let url = format!("https://api.telegram.org/bot{}/sendMessage", bot_token);
+    let bot = Bot::with_client(bot_token, client.clone());
 
     // let thread_id = match mode {
     //     Mode::Survival => 2, // Survival thread ID
     //     Mode::FCFS => 14,     // FCFS thread ID
     // };
 
-    let payload = TelegramMessage {
-        chat_id: chat_id,
-        message_thread_id: thread_id, // The "Winners List" thread
-        text: post,
-        parse_mode: "HTML".to_string(),
-        disable_web_page_preview: Some(true), // Keeps the list clean if seeds look like links
-    };
+    let mut message = SendMessage::new(chat_id, post)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true); // Keeps the list clean if seeds look like links
 
-    let response = client
-        .post(url)
-        .json(&payload)
-        .send()
-        .await?;
-
-    // 3. Robust Error Logging
-    if !response.status().is_success() {
-        let status = response.status();
-        let err_body = response.text().await.unwrap_or_default();
-        eprintln!("Telegram API Error (Status {}): {}", status, err_body);
-        return Ok(());
+    if let Some(thread_id) = thread_id {
+        message = message.thread(thread_id as i64); // The "Winners List" thread
     }
 
-    // println!("Successfully posted winners to Telegram Topic {}", thread_id);
-    Ok(())
+    send_logged(&bot, &message).await
 }
 
 pub async fn notify(
@@ -407,132 +876,163 @@ pub async fn notify(
 
     client: &Client,
 ) -> Result<(), reqwest::Error> {
-    let url = format!("https://api.telegram.org/bot{}/sendMessage", bot_token);
-
-    let payload = serde_json::json!({
-        "chat_id": user_id, // In your app, user_id is usually the Telegram chat_id
-        "text": text,
-        "parse_mode": "HTML"
-    });
-
-    client
-        .post(url)
-        .json(&payload)
-        .send()
-        .await?;
+    let bot = Bot::with_client(bot_token, client.clone());
+
+    // In your app, user_id is usually the Telegram chat_id
+    let message = SendMessage::new(user_id, text)
+        .parse_mode(ParseMode::Html);
 
-    Ok(())
+    send_logged(&bot, &message).await
 }
 
-// pub async fn profile_picture(
-//     client: &Client,
-//     Query(params): Query<ProfilePictureParams>,
-// ) -> Result<Response, AppError> {
-//     let bot_token = env::get("TOKEN")
-//         .expect("TOKEN IS NOT SET");
+// Transport errors are returned, API errors (blocked bot, bad markup...) only logged.
+// Text over 4096 characters is split into several messages
+async fn send_logged(bot: &Bot, message: &SendMessage) -> Result<(), reqwest::Error> {
+    match bot.send_long_message(message).await {
+        Ok(_) => Ok(()),
+        Err(TelegramError::Http(err)) => Err(err),
+        Err(err) => {
+            eprintln!("{}", err);
+            Ok(())
+        }
+    }
+}
 
-//     let user_id = params.user_id;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOT_TOKEN: &str = "123456:TEST-TOKEN";
+    const BOT_ID: i64 = 123456;
+    // Public key of the Ed25519 seed [7; 32] that signed INIT_DATA
+    const PUBLIC_KEY: &str = "ea4a6c63e29c520abef5507b132ec5f9954776aebebe7b92421eea691446d22c";
+    const HASH: &str = "8bb3e02b242a889fc6a627cfeb06a72c720638751552e6eb7d46c5ba80c23457";
+    const INIT_DATA: &str = concat!(
+        "auth_date=1700000000&query_id=AAHdF6IQAAAAAN0XohDhrOrc",
+        "&user=%7B%22id%22%3A42%2C%22first_name%22%3A%22Ann%22%2C%22username%22%3A%22ann%22%2C%22language_code%22%3A%22en%22%7D",
+        "&signature=x2fx58atDINP2i5L1NuESJyQKiT8re23Rt1flc_sUsqrAeXykQJwJeVrWyLAvAY9iH8gJ-fQNN3f7pUFMwQKDQ",
+        "&hash=8bb3e02b242a889fc6a627cfeb06a72c720638751552e6eb7d46c5ba80c23457",
+    );
+
+    // The vectors are from 2023, so they are only fresh under a very long max age
+    fn lenient() -> InitDataPolicy {
+        InitDataPolicy { max_age_secs: 100 * 365 * 24 * 3600, ..Default::default() }
+    }
 
-//     let url = format!(
-//         "https://api.telegram.org/bot{}/getUserProfilePhotos?user_id={}",
-//         bot_token, user_id
-//     );
-//     let response = client
-//         .get(&url)
-//         .send()
-//         .await?;
-//         // .map_err(AppError::from);
-
-//     if response.is_err() {
-//         println!("Error fetching user profile photos: {:?}", response.as_ref().err());
-//         return Err(AppError::from(response.err().unwrap()));
-//     }
+    fn widget() -> LoginWidgetData {
+        LoginWidgetData {
+            id: 42,
+            first_name: "Ann".to_string(),
+            last_name: None,
+            username: Some("ann".to_string()),
+            photo_url: None,
+            auth_date: 1_700_000_000,
+            hash: "2f72a46af165bbd42b1ef82145575f563583a3b8550a0a6a1d6e1e7bd4a1f049".to_string(),
+        }
+    }
 
-//     let response = response
-//         .unwrap()
-//         .json()
-//         .await
-//         .map_err(AppError::from);
+    #[test]
+    fn init_data_hash_accepts_known_vector() {
+        let init_data = validate_and_parse_init_data_policy(INIT_DATA, BOT_TOKEN, &lenient()).unwrap().unwrap();
+        let user = init_data.user.unwrap();
+        assert_eq!((user.id, user.username.as_str()), (42, "ann"));
+        assert_eq!(init_data.query_id.as_deref(), Some("AAHdF6IQAAAAAN0XohDhrOrc"));
+        assert_eq!(init_data.auth_date, 1_700_000_000);
+    }
 
-//     if response.is_err() {
-//         println!("Error parsing user profile photos response: {:?}", response.as_ref().err());
-//         return Err(AppError::from(response.err().unwrap()));
-//     }
+    #[test]
+    fn init_data_hash_rejects_tampering() {
+        let tampered = INIT_DATA.replace("%22id%22%3A42", "%22id%22%3A43");
+        assert!(validate_and_parse_init_data_policy(&tampered, BOT_TOKEN, &lenient()).unwrap().is_none());
 
-//     let response: TelegramResponse<UserProfilePhotos> = response.unwrap();
+        let other_token = validate_and_parse_init_data_policy(INIT_DATA, "123456:OTHER", &lenient()).unwrap();
+        assert!(other_token.is_none());
 
-//     if !response.ok || response.result.is_none() || response.result.as_ref().unwrap().photos.is_empty() {
-//         // println!("No profile photos found for user_id: {}", user_id);
-//         return Err(AppError::NotFound("No profile picture found".to_string()));
-//     }
+        let bad_hash = INIT_DATA.replace(HASH, &HASH.replace('8', "9"));
+        assert!(validate_and_parse_init_data_policy(&bad_hash, BOT_TOKEN, &lenient()).unwrap().is_none());
 
-//     let file_id = response.result.unwrap().photos[0].last().unwrap().file_id.clone();
+        let not_hex = INIT_DATA.replace(HASH, "zz");
+        assert!(validate_and_parse_init_data_policy(&not_hex, BOT_TOKEN, &lenient()).is_err());
 
-//     let url = format!(
-//         "https://api.telegram.org/bot{}/getFile?file_id={}",
-//         bot_token, &file_id
-//     );
-//     let response: TelegramResponse<FileResponse> = state.client
-//         .get(&url)
-//         .send()
-//         .await
-//         .map_err(AppError::from)?
-//         .json()
-//         .await
-//         .map_err(AppError::from)?;
-
-//     if !response.ok || response.result.is_none() {
-//         println!("Failed to get file path for file_id: {}", file_id);
-//         return Err(AppError::Internal("Failed to get file path".to_string()));
-//     }
+        let no_hash = INIT_DATA.split("&hash=").next().unwrap();
+        assert!(validate_and_parse_init_data_policy(no_hash, BOT_TOKEN, &lenient()).is_err());
+    }
 
-//     let file_path = response.result.unwrap().file_path;
-
-//     let file_url = format!("https://api.telegram.org/file/bot{}/{}", bot_token, file_path);
-//     let response = state.client
-//         .get(&file_url)
-//         .send()
-//         .await
-//         .map_err(AppError::from)?;
-
-//     let content_type = {
-//         let from_headers = response
-//             .headers()
-//             .get("content-type")
-//             .and_then(|v| v.to_str().ok())
-//             .map(String::from);
-
-//         match from_headers {
-//             Some(ct) if ct != "application/octet-stream" => ct,
-//             _ => {
-//                 if file_path.ends_with(".jpg") || file_path.ends_with(".jpeg") {
-//                     "image/jpeg".to_string()
-//                 } else if file_path.ends_with(".png") {
-//                     "image/png".to_string()
-//                 } else if file_path.ends_with(".svg") {
-//                     "image/svg+xml".to_string()
-//                 } else {
-//                     "image/jpeg".to_string() // Default
-//                 }
-//             }
-//         }
-//     };
-//     let image_bytes = response.bytes().await.map_err(AppError::from)?;
-
-//     let cache_duration_days = 7;
-
-//     Ok((
-//         StatusCode::OK,
-//         [
-//             (header::CONTENT_TYPE, content_type),
-//             (
-//                 header::CACHE_CONTROL,
-//                 format!("public, max-age={}, stale-while-revalidate=604800", 86400 * cache_duration_days)
-//             ),
-//             (header::ETAG, format!("\"{}\"", file_id)), // file_id is stable per photo
-//         ],
-//         // [(header::CONTENT_TYPE, content_type)],
-//         image_bytes.to_vec(),
-//     )
-//         .into_response())
-// }
\ No newline at end of file
+    #[test]
+    fn init_data_rejects_stale_auth_date() {
+        assert!(!validate_init_data(INIT_DATA, BOT_TOKEN).unwrap());
+
+        let age = chrono::Utc::now().timestamp() - 1_700_000_000;
+        let just_expired = InitDataPolicy { max_age_secs: age - 60, ..Default::default() };
+        assert!(validate_and_parse_init_data_policy(INIT_DATA, BOT_TOKEN, &just_expired).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn init_data_is_claimed_once() {
+        let replay = MemoryReplayStore::new();
+        let policy = lenient();
+
+        assert!(validate_init_data_with(INIT_DATA, BOT_TOKEN, &policy, Some(&replay)).await.unwrap());
+        assert!(!validate_init_data_with(INIT_DATA, BOT_TOKEN, &policy, Some(&replay)).await.unwrap());
+
+        // Same payload with the hash in upper case
+        let upper = INIT_DATA.replace(HASH, &HASH.to_ascii_uppercase());
+        assert!(!validate_init_data_with(&upper, BOT_TOKEN, &policy, Some(&replay)).await.unwrap());
+    }
+
+    #[test]
+    fn replay_store_expires_claims() {
+        let replay = MemoryReplayStore::new();
+        let now = chrono::Utc::now().timestamp();
+        assert!(replay.claim_now("a", now - 1));
+        assert!(replay.claim_now("a", now + 60));
+        assert!(!replay.claim_now("a", now + 60));
+
+        for i in 0..REPLAY_STORE_SWEEP_AT {
+            replay.claim_now(&i.to_string(), now - 1);
+        }
+        // Swept once the map reached the threshold, the last expired key came after
+        assert!(replay.claim_now("b", now + 60));
+        assert_eq!(replay.seen.lock().unwrap().map.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn init_data_signature() {
+        let policy = lenient();
+        assert!(validate_init_data_signature_with(INIT_DATA, BOT_ID, PUBLIC_KEY, &policy, None).await.unwrap());
+        assert!(!validate_init_data_signature_with(INIT_DATA, BOT_ID + 1, PUBLIC_KEY, &policy, None).await.unwrap());
+        assert!(!validate_init_data_signature_with(INIT_DATA, BOT_ID, TELEGRAM_PUBLIC_KEY, &policy, None).await.unwrap());
+        assert!(!validate_init_data_signature(INIT_DATA, BOT_ID).unwrap());
+
+        // hash isn't signed, so changing it keeps the signature valid
+        let rehashed = INIT_DATA.replace(HASH, "00");
+        assert!(validate_init_data_signature_with(&rehashed, BOT_ID, PUBLIC_KEY, &policy, None).await.unwrap());
+
+        let tampered = INIT_DATA.replace("query_id=AAH", "query_id=BAH");
+        assert!(!validate_init_data_signature_with(&tampered, BOT_ID, PUBLIC_KEY, &policy, None).await.unwrap());
+
+        assert!(!validate_init_data_signature_with(INIT_DATA, BOT_ID, PUBLIC_KEY, &InitDataPolicy::default(), None).await.unwrap());
+
+        let replay = MemoryReplayStore::new();
+        assert!(validate_init_data_signature_with(INIT_DATA, BOT_ID, PUBLIC_KEY, &policy, Some(&replay)).await.unwrap());
+        assert!(!validate_init_data_signature_with(INIT_DATA, BOT_ID, PUBLIC_KEY, &policy, Some(&replay)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn login_widget() {
+        let policy = lenient();
+        assert!(validate_login_widget_with(&widget(), BOT_TOKEN, &policy, None).await.unwrap());
+        assert!(!validate_login_widget(&widget(), BOT_TOKEN).unwrap());
+
+        let tampered = LoginWidgetData { id: 43, ..widget() };
+        assert!(!validate_login_widget_with(&tampered, BOT_TOKEN, &policy, None).await.unwrap());
+
+        let added = LoginWidgetData { last_name: Some("Lee".to_string()), ..widget() };
+        assert!(!validate_login_widget_with(&added, BOT_TOKEN, &policy, None).await.unwrap());
+
+        let replay = MemoryReplayStore::new();
+        assert!(validate_login_widget_with(&widget(), BOT_TOKEN, &policy, Some(&replay)).await.unwrap());
+        let upper = LoginWidgetData { hash: widget().hash.to_ascii_uppercase(), ..widget() };
+        assert!(!validate_login_widget_with(&upper, BOT_TOKEN, &policy, Some(&replay)).await.unwrap());
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::bot::{Bot, TelegramError};
+
+pub const CACHE_MAX_AGE_SECS: u64 = 86400 * 7;
+
+#[derive(Debug, Clone)]
+pub struct Avatar {
+    pub file_unique_id: String, // stable per photo, used as the ETag
+    pub content_type: String,
+    pub bytes: Arc<Vec<u8>>,
+}
+
+impl Avatar {
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.file_unique_id)
+    }
+}
+
+// Downloads the current profile photo in its largest size, None if the user has
+// no photo or hides it from the bot
+pub async fn fetch_avatar(bot: &Bot, user_id: i64) -> Result<Option<Avatar>, TelegramError> {
+    let photos = bot.get_user_profile_photos(user_id, None, Some(1)).await?;
+
+    let Some(photo) = photos.photos.first().and_then(|sizes| sizes.last()) else {
+        return Ok(None);
+    };
+
+    let file = bot.get_file(&photo.file_id).await?;
+    let Some(file_path) = file.file_path else {
+        return Ok(None);
+    };
+
+    let bytes = bot.download_file(&file_path).await?;
+
+    Ok(Some(Avatar {
+        file_unique_id: photo.file_unique_id.clone(),
+        content_type: content_type(&file_path).to_string(),
+        bytes: Arc::new(bytes),
+    }))
+}
+
+// Only raster types browsers render inertly, anything else is served as a download
+fn content_type(file_path: &str) -> &'static str {
+    let extension = file_path.rsplit('.').next().unwrap_or_default().to_lowercase();
+    match extension.as_str() {
+        "jpg" | "jpeg" => "image/jpeg", // profile photos are jpg
+        "png" => "image/png",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+pub trait AvatarCache: Send + Sync + 'static {
+    // None on a miss, Some(None) if the user is known to have no photo
+    fn get(&self, user_id: i64) -> impl Future<Output = Option<Option<Avatar>>> + Send;
+    fn put(&self, user_id: i64, avatar: Option<&Avatar>) -> impl Future<Output = ()> + Send;
+}
+
+struct CachedAvatar {
+    avatar: Option<Avatar>,
+    stored_at: Instant,
+    used: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<i64, CachedAvatar>,
+    order: BTreeMap<u64, i64>, // last use -> user_id, oldest first
+    tick: u64,
+    bytes: usize,
+}
+
+impl Lru {
+    fn remove(&mut self, user_id: i64) {
+        if let Some(entry) = self.entries.remove(&user_id) {
+            self.order.remove(&entry.used);
+            self.bytes -= avatar_len(entry.avatar.as_ref());
+        }
+    }
+}
+
+fn avatar_len(avatar: Option<&Avatar>) -> usize {
+    avatar.map_or(0, |avatar| avatar.bytes.len())
+}
+
+// In-memory LRU bounded by entry count and total bytes, entries expire after `ttl`
+// so changed photos show up eventually
+pub struct MemoryAvatarCache {
+    lru: Mutex<Lru>,
+    max_entries: usize,
+    max_bytes: usize,
+    ttl: Duration,
+}
+
+impl MemoryAvatarCache {
+    pub fn new(max_entries: usize, max_bytes: usize, ttl: Duration) -> Self {
+        MemoryAvatarCache {
+            lru: Mutex::new(Lru::default()),
+            max_entries: max_entries.max(1),
+            max_bytes,
+            ttl,
+        }
+    }
+}
+
+impl Default for MemoryAvatarCache {
+    fn default() -> Self {
+        Self::new(10_000, 64 * 1024 * 1024, Duration::from_secs(3600))
+    }
+}
+
+impl AvatarCache for MemoryAvatarCache {
+    async fn get(&self, user_id: i64) -> Option<Option<Avatar>> {
+        let mut lru = self.lru.lock().unwrap_or_else(|e| e.into_inner());
+
+        let expired = lru.entries.get(&user_id)?.stored_at.elapsed() > self.ttl;
+        if expired {
+            lru.remove(user_id);
+            return None;
+        }
+
+        lru.tick += 1;
+        let tick = lru.tick;
+        let entry = lru.entries.get_mut(&user_id)?;
+        let previous = std::mem::replace(&mut entry.used, tick);
+        let avatar = entry.avatar.clone();
+
+        lru.order.remove(&previous);
+        lru.order.insert(tick, user_id);
+        Some(avatar)
+    }
+
+    async fn put(&self, user_id: i64, avatar: Option<&Avatar>) {
+        let len = avatar_len(avatar);
+        if len > self.max_bytes {
+            return;
+        }
+
+        let mut lru = self.lru.lock().unwrap_or_else(|e| e.into_inner());
+        lru.remove(user_id);
+
+        // Evict least recently used until the new photo fits
+        while lru.entries.len() >= self.max_entries || lru.bytes + len > self.max_bytes {
+            let Some((_, oldest)) = lru.order.pop_first() else {
+                break;
+            };
+            lru.remove(oldest);
+        }
+
+        lru.tick += 1;
+        let used = lru.tick;
+        lru.bytes += len;
+        lru.order.insert(used, user_id);
+        lru.entries.insert(user_id, CachedAvatar { avatar: avatar.cloned(), stored_at: Instant::now(), used });
+    }
+}
+
+// Keeps photos in a bucket under "<prefix>/<user_id>", shared between instances
+#[cfg(feature = "s3")]
+pub struct S3AvatarCache {
+    client: crate::S3::Client,
+    bucket: String,
+    prefix: String,
+    ttl: Duration,
+}
+
+#[cfg(feature = "s3")]
+impl S3AvatarCache {
+    pub fn new(client: crate::S3::Client, bucket: impl Into<String>, prefix: impl Into<String>, ttl: Duration) -> Self {
+        S3AvatarCache {
+            client,
+            bucket: bucket.into(),
+            prefix: prefix.into().trim_end_matches('/').to_string(),
+            ttl,
+        }
+    }
+
+    fn key(&self, user_id: i64) -> String {
+        format!("{}/{}", self.prefix, user_id)
+    }
+}
+
+#[cfg(feature = "s3")]
+const FILE_UNIQUE_ID_METADATA: &str = "file-unique-id";
+
+#[cfg(feature = "s3")]
+impl AvatarCache for S3AvatarCache {
+    async fn get(&self, user_id: i64) -> Option<Option<Avatar>> {
+        let output = self.client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(user_id))
+            .send()
+            .await
+            .ok()?; // missing objects are cache misses
+
+        let stored_at = output.last_modified()?.secs();
+        if chrono::Utc::now().timestamp() - stored_at > self.ttl.as_secs() as i64 {
+            return None;
+        }
+
+        // Users without a photo are stored as an empty object without the id
+        let Some(file_unique_id) = output.metadata().and_then(|m| m.get(FILE_UNIQUE_ID_METADATA)).cloned() else {
+            return Some(None);
+        };
+        let content_type = output.content_type().unwrap_or("image/jpeg").to_string();
+        let bytes = output.body.collect().await.ok()?.into_bytes().to_vec();
+
+        Some(Some(Avatar { file_unique_id, content_type, bytes: Arc::new(bytes) }))
+    }
+
+    async fn put(&self, user_id: i64, avatar: Option<&Avatar>) {
+        let request = self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.key(user_id));
+
+        let request = match avatar {
+            Some(avatar) => request
+                .body(aws_sdk_s3::primitives::ByteStream::from(avatar.bytes.to_vec()))
+                .content_type(&avatar.content_type)
+                .metadata(FILE_UNIQUE_ID_METADATA, &avatar.file_unique_id),
+            None => request.body(aws_sdk_s3::primitives::ByteStream::from(Vec::new())),
+        };
+
+        let result = request.send().await;
+
+        if let Err(err) = result {
+            eprintln!("Failed to cache avatar of {} in S3: {:?}", user_id, err);
+        }
+    }
+}
+
+// Avatar lookups through a cache, backing the profile picture endpoint
+pub struct ProfilePictures<C> {
+    bot: Bot,
+    cache: C,
+    max_age_secs: u64,
+}
+
+impl<C: AvatarCache> ProfilePictures<C> {
+    pub fn new(bot: Bot, cache: C) -> Self {
+        ProfilePictures { bot, cache, max_age_secs: CACHE_MAX_AGE_SECS }
+    }
+
+    // Cache-Control max-age sent to clients
+    pub fn max_age(mut self, seconds: u64) -> Self {
+        self.max_age_secs = seconds;
+        self
+    }
+
+    pub async fn get(&self, user_id: i64) -> Result<Option<Avatar>, TelegramError> {
+        if let Some(avatar) = self.cache.get(user_id).await {
+            return Ok(avatar);
+        }
+
+        // Users without a photo are cached too, so they don't cost a Bot API call each time
+        let avatar = fetch_avatar(&self.bot, user_id).await?;
+        self.cache.put(user_id, avatar.as_ref()).await;
+        Ok(avatar)
+    }
+}
+
+#[cfg(feature = "axum")]
+mod handler {
+    use std::sync::Arc;
+
+    use axum::{
+        Router,
+        extract::{Query, State},
+        http::{HeaderMap, StatusCode, header},
+        response::{IntoResponse, Response},
+        routing::get,
+    };
+    use serde::Deserialize;
+
+    use super::{AvatarCache, ProfilePictures};
+
+    #[derive(Debug, Deserialize)]
+    pub struct ProfilePictureParams {
+        pub user_id: i64,
+    }
+
+    // GET `path`?user_id=<id> serving the user's avatar
+    pub fn router<C: AvatarCache>(pictures: ProfilePictures<C>, path: &str) -> Router {
+        Router::new()
+            .route(path, get(profile_picture::<C>))
+            .with_state(Arc::new(pictures))
+    }
+
+    pub async fn profile_picture<C: AvatarCache>(
+        State(pictures): State<Arc<ProfilePictures<C>>>,
+        Query(params): Query<ProfilePictureParams>,
+        headers: HeaderMap,
+    ) -> Response {
+        let avatar = match pictures.get(params.user_id).await {
+            Ok(Some(avatar)) => avatar,
+            // Let clients remember missing photos for a while too
+            Ok(None) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    [(header::CACHE_CONTROL, "public, max-age=3600")],
+                    "No profile picture found",
+                )
+                    .into_response();
+            }
+            Err(err) => {
+                eprintln!("Failed to fetch avatar of {}: {}", params.user_id, err);
+                return (StatusCode::BAD_GATEWAY, "Failed to fetch profile picture").into_response();
+            }
+        };
+
+        let etag = avatar.etag();
+        let cache_control = format!(
+            "public, max-age={}, stale-while-revalidate={}",
+            pictures.max_age_secs,
+            86400 * 7
+        );
+
+        let not_modified = headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+
+        if not_modified {
+            return (
+                StatusCode::NOT_MODIFIED,
+                [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control)],
+            )
+                .into_response();
+        }
+
+        (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, avatar.content_type.clone()),
+                (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+                (header::CACHE_CONTROL, cache_control),
+                (header::ETAG, etag),
+            ],
+            avatar.bytes.to_vec(),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(feature = "axum")]
+pub use handler::{ProfilePictureParams, profile_picture, router};
@@ -0,0 +1,673 @@
+use reqwest::{Client, multipart};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::env;
+
+use super::keyboard::{InlineKeyboardMarkup, ReplyMarkup};
+use super::types::{
+    ChatId, ChatMember, Edited, File, Message, ParseMode, ResponseParameters, TelegramResponse,
+    Update, UserProfilePhotos,
+};
+
+pub const API_URL: &str = "https://api.telegram.org";
+
+#[derive(Debug)]
+pub enum TelegramError {
+    // {"ok": false, ...} from the Bot API
+    Api {
+        error_code: i32,
+        description: String,
+        parameters: ResponseParameters,
+    },
+    Http(reqwest::Error),
+    // Body that isn't a Bot API response (proxy errors, HTML pages...)
+    Invalid(String),
+}
+
+impl TelegramError {
+    // Seconds to wait before retrying after a 429
+    pub fn retry_after(&self) -> Option<i64> {
+        match self {
+            TelegramError::Api { parameters, .. } => parameters.retry_after,
+            _ => None,
+        }
+    }
+
+    pub fn error_code(&self) -> Option<i32> {
+        match self {
+            TelegramError::Api { error_code, .. } => Some(*error_code),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for TelegramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TelegramError::Api { error_code, description, .. } => write!(f, "Telegram API error {}: {}", error_code, description),
+            TelegramError::Http(err) => write!(f, "Telegram request failed: {}", err),
+            TelegramError::Invalid(body) => write!(f, "Unexpected Telegram response: {}", body),
+        }
+    }
+}
+
+impl std::error::Error for TelegramError {}
+
+// The request URL contains the bot token, drop it so logged errors don't leak it
+impl From<reqwest::Error> for TelegramError {
+    fn from(err: reqwest::Error) -> Self {
+        TelegramError::Http(err.without_url())
+    }
+}
+
+// Photo or document to send: a file_id / URL Telegram already knows, or bytes to upload
+#[derive(Debug, Clone)]
+pub enum InputFile {
+    Id(String),
+    Upload {
+        file_name: String,
+        bytes: Vec<u8>,
+        mime_type: Option<String>,
+    },
+}
+
+impl InputFile {
+    pub fn id(file_id: impl Into<String>) -> Self {
+        InputFile::Id(file_id.into())
+    }
+
+    pub fn url(url: impl Into<String>) -> Self {
+        InputFile::Id(url.into())
+    }
+
+    pub fn bytes(file_name: impl Into<String>, bytes: Vec<u8>) -> Self {
+        InputFile::Upload { file_name: file_name.into(), bytes, mime_type: None }
+    }
+
+    pub fn mime_type(self, mime: impl Into<String>) -> Self {
+        match self {
+            InputFile::Upload { file_name, bytes, .. } => InputFile::Upload { file_name, bytes, mime_type: Some(mime.into()) },
+            file => file,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SendMessage {
+    pub chat_id: ChatId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_thread_id: Option<i64>,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parse_mode: Option<ParseMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_web_page_preview: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_notification: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protect_content: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_markup: Option<ReplyMarkup>,
+}
+
+impl SendMessage {
+    pub fn new(chat_id: impl Into<ChatId>, text: impl Into<String>) -> Self {
+        SendMessage {
+            chat_id: chat_id.into(),
+            message_thread_id: None,
+            text: text.into(),
+            parse_mode: None,
+            disable_web_page_preview: None,
+            disable_notification: None,
+            protect_content: None,
+            reply_markup: None,
+        }
+    }
+
+    pub fn thread(mut self, message_thread_id: i64) -> Self {
+        self.message_thread_id = Some(message_thread_id);
+        self
+    }
+
+    pub fn parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.parse_mode = Some(parse_mode);
+        self
+    }
+
+    pub fn disable_web_page_preview(mut self, disable: bool) -> Self {
+        self.disable_web_page_preview = Some(disable);
+        self
+    }
+
+    pub fn silent(mut self) -> Self {
+        self.disable_notification = Some(true);
+        self
+    }
+
+    pub fn protect_content(mut self) -> Self {
+        self.protect_content = Some(true);
+        self
+    }
+
+    pub fn reply_markup(mut self, reply_markup: impl Into<ReplyMarkup>) -> Self {
+        self.reply_markup = Some(reply_markup.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EditMessageText {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<ChatId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inline_message_id: Option<String>,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parse_mode: Option<ParseMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_web_page_preview: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+impl EditMessageText {
+    pub fn new(chat_id: impl Into<ChatId>, message_id: i64, text: impl Into<String>) -> Self {
+        EditMessageText {
+            chat_id: Some(chat_id.into()),
+            message_id: Some(message_id),
+            inline_message_id: None,
+            text: text.into(),
+            parse_mode: None,
+            disable_web_page_preview: None,
+            reply_markup: None,
+        }
+    }
+
+    pub fn inline(inline_message_id: impl Into<String>, text: impl Into<String>) -> Self {
+        EditMessageText {
+            chat_id: None,
+            message_id: None,
+            inline_message_id: Some(inline_message_id.into()),
+            text: text.into(),
+            parse_mode: None,
+            disable_web_page_preview: None,
+            reply_markup: None,
+        }
+    }
+
+    pub fn parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.parse_mode = Some(parse_mode);
+        self
+    }
+
+    pub fn disable_web_page_preview(mut self, disable: bool) -> Self {
+        self.disable_web_page_preview = Some(disable);
+        self
+    }
+
+    pub fn reply_markup(mut self, reply_markup: impl Into<InlineKeyboardMarkup>) -> Self {
+        self.reply_markup = Some(reply_markup.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SendPhoto {
+    pub chat_id: ChatId,
+    #[serde(skip)]
+    pub photo: InputFile,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_thread_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parse_mode: Option<ParseMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub has_spoiler: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_notification: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_markup: Option<ReplyMarkup>,
+}
+
+impl SendPhoto {
+    pub fn new(chat_id: impl Into<ChatId>, photo: InputFile) -> Self {
+        SendPhoto {
+            chat_id: chat_id.into(),
+            photo,
+            message_thread_id: None,
+            caption: None,
+            parse_mode: None,
+            has_spoiler: None,
+            disable_notification: None,
+            reply_markup: None,
+        }
+    }
+
+    pub fn thread(mut self, message_thread_id: i64) -> Self {
+        self.message_thread_id = Some(message_thread_id);
+        self
+    }
+
+    pub fn caption(mut self, caption: impl Into<String>) -> Self {
+        self.caption = Some(caption.into());
+        self
+    }
+
+    pub fn parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.parse_mode = Some(parse_mode);
+        self
+    }
+
+    pub fn spoiler(mut self) -> Self {
+        self.has_spoiler = Some(true);
+        self
+    }
+
+    pub fn silent(mut self) -> Self {
+        self.disable_notification = Some(true);
+        self
+    }
+
+    pub fn reply_markup(mut self, reply_markup: impl Into<ReplyMarkup>) -> Self {
+        self.reply_markup = Some(reply_markup.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SendDocument {
+    pub chat_id: ChatId,
+    #[serde(skip)]
+    pub document: InputFile,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_thread_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parse_mode: Option<ParseMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_notification: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_markup: Option<ReplyMarkup>,
+}
+
+impl SendDocument {
+    pub fn new(chat_id: impl Into<ChatId>, document: InputFile) -> Self {
+        SendDocument {
+            chat_id: chat_id.into(),
+            document,
+            message_thread_id: None,
+            caption: None,
+            parse_mode: None,
+            disable_notification: None,
+            reply_markup: None,
+        }
+    }
+
+    pub fn thread(mut self, message_thread_id: i64) -> Self {
+        self.message_thread_id = Some(message_thread_id);
+        self
+    }
+
+    pub fn caption(mut self, caption: impl Into<String>) -> Self {
+        self.caption = Some(caption.into());
+        self
+    }
+
+    pub fn parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.parse_mode = Some(parse_mode);
+        self
+    }
+
+    pub fn silent(mut self) -> Self {
+        self.disable_notification = Some(true);
+        self
+    }
+
+    pub fn reply_markup(mut self, reply_markup: impl Into<ReplyMarkup>) -> Self {
+        self.reply_markup = Some(reply_markup.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AnswerCallbackQuery {
+    pub callback_query_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_alert: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_time: Option<i32>,
+}
+
+impl AnswerCallbackQuery {
+    pub fn new(callback_query_id: impl Into<String>) -> Self {
+        AnswerCallbackQuery {
+            callback_query_id: callback_query_id.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.text = Some(text.into());
+        self
+    }
+
+    pub fn alert(mut self, text: impl Into<String>) -> Self {
+        self.text = Some(text.into());
+        self.show_alert = Some(true);
+        self
+    }
+
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    pub fn cache_time(mut self, seconds: i32) -> Self {
+        self.cache_time = Some(seconds);
+        self
+    }
+}
+
+#[derive(Clone)]
+pub struct Bot {
+    token: String,
+    base_url: String,
+    client: Client,
+}
+
+impl std::fmt::Debug for Bot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the token
+        f.debug_struct("Bot")
+            .field("id", &self.id())
+            .field("base_url", &self.base_url)
+            .finish()
+    }
+}
+
+impl Bot {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self::with_client(token, Client::new())
+    }
+
+    pub fn with_client(token: impl Into<String>, client: Client) -> Self {
+        Bot {
+            token: token.into(),
+            base_url: API_URL.to_string(),
+            client,
+        }
+    }
+
+    // TOKEN, and TELEGRAM_API_URL for a local Bot API server or mock
+    pub fn from_env() -> Result<Self, std::env::VarError> {
+        let bot = Self::new(env::get("TOKEN")?);
+        Ok(match env::get("TELEGRAM_API_URL") {
+            Ok(url) => bot.base_url(url),
+            Err(_) => bot,
+        })
+    }
+
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    // Bot tokens look like "<bot_id>:<secret>"
+    pub fn id(&self) -> Option<i64> {
+        self.token.split(':').next()?.parse().ok()
+    }
+
+    pub fn method_url(&self, method: &str) -> String {
+        format!("{}/bot{}/{}", self.base_url, self.token, method)
+    }
+
+    pub fn file_url(&self, file_path: &str) -> String {
+        format!("{}/file/bot{}/{}", self.base_url, self.token, file_path)
+    }
+
+    // Calls any Bot API method with a JSON body
+    pub async fn call<P, R>(&self, method: &str, params: &P) -> Result<R, TelegramError>
+    where
+        P: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        let response = self.client
+            .post(self.method_url(method))
+            .json(params)
+            .send()
+            .await?;
+
+        Self::parse(response).await
+    }
+
+    // Calls a method that takes a file in `field`, uploading it as multipart if needed
+    pub async fn call_with_file<P, R>(
+        &self,
+        method: &str,
+        params: &P,
+        field: &str,
+        file: &InputFile,
+    ) -> Result<R, TelegramError>
+    where
+        P: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        let mut params = serde_json::to_value(params)
+            .map_err(|e| TelegramError::Invalid(e.to_string()))?;
+
+        let (file_name, bytes, mime_type) = match file {
+            InputFile::Id(id) => {
+                if let Some(params) = params.as_object_mut() {
+                    params.insert(field.to_string(), id.clone().into());
+                }
+                return self.call(method, &params).await;
+            }
+            InputFile::Upload { file_name, bytes, mime_type } => (file_name, bytes, mime_type),
+        };
+
+        // Every other field goes as text, nested objects JSON-encoded
+        let mut form = multipart::Form::new();
+        if let Some(params) = params.as_object() {
+            for (key, value) in params {
+                let text = match value {
+                    serde_json::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                form = form.text(key.clone(), text);
+            }
+        }
+
+        let mut part = multipart::Part::bytes(bytes.clone()).file_name(file_name.clone());
+        if let Some(mime_type) = mime_type {
+            part = part.mime_str(mime_type)?;
+        }
+        form = form.part(field.to_string(), part);
+
+        let response = self.client
+            .post(self.method_url(method))
+            .multipart(form)
+            .send()
+            .await?;
+
+        Self::parse(response).await
+    }
+
+    async fn parse<R: DeserializeOwned>(response: reqwest::Response) -> Result<R, TelegramError> {
+        let status = response.status();
+        let body = response.text().await?;
+
+        let parsed: TelegramResponse<R> = serde_json::from_str(&body)
+            .map_err(|_| TelegramError::Invalid(format!("({}) {}", status, body)))?;
+
+        match parsed {
+            TelegramResponse { ok: true, result: Some(result), .. } => Ok(result),
+            TelegramResponse { error_code, description, parameters, .. } => Err(TelegramError::Api {
+                error_code: error_code.unwrap_or(status.as_u16() as i32),
+                description: description.unwrap_or_default(),
+                parameters: parameters.unwrap_or_default(),
+            }),
+        }
+    }
+
+    pub async fn send_message(&self, params: &SendMessage) -> Result<Message, TelegramError> {
+        self.call("sendMessage", params).await
+    }
+
+    pub async fn edit_message_text(&self, params: &EditMessageText) -> Result<Edited, TelegramError> {
+        self.call("editMessageText", params).await
+    }
+
+    pub async fn delete_message(&self, chat_id: impl Into<ChatId>, message_id: i64) -> Result<bool, TelegramError> {
+        self.call("deleteMessage", &serde_json::json!({
+            "chat_id": chat_id.into(),
+            "message_id": message_id,
+        })).await
+    }
+
+    pub async fn send_photo(&self, params: &SendPhoto) -> Result<Message, TelegramError> {
+        self.call_with_file("sendPhoto", params, "photo", &params.photo).await
+    }
+
+    pub async fn send_document(&self, params: &SendDocument) -> Result<Message, TelegramError> {
+        self.call_with_file("sendDocument", params, "document", &params.document).await
+    }
+
+    pub async fn answer_callback_query(&self, params: &AnswerCallbackQuery) -> Result<bool, TelegramError> {
+        self.call("answerCallbackQuery", params).await
+    }
+
+    pub async fn get_chat_member(&self, chat_id: impl Into<ChatId>, user_id: i64) -> Result<ChatMember, TelegramError> {
+        self.call("getChatMember", &serde_json::json!({
+            "chat_id": chat_id.into(),
+            "user_id": user_id,
+        })).await
+    }
+
+    pub async fn get_user_profile_photos(
+        &self,
+        user_id: i64,
+        offset: Option<i32>,
+        limit: Option<i32>,
+    ) -> Result<UserProfilePhotos, TelegramError> {
+        #[derive(Serialize)]
+        struct Params {
+            user_id: i64,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            offset: Option<i32>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            limit: Option<i32>,
+        }
+
+        self.call("getUserProfilePhotos", &Params { user_id, offset, limit }).await
+    }
+
+    pub async fn get_file(&self, file_id: &str) -> Result<File, TelegramError> {
+        self.call("getFile", &serde_json::json!({ "file_id": file_id })).await
+    }
+
+    // Long polling, `timeout` in seconds. Updates below `offset` are confirmed and dropped by Telegram.
+    // Updates that fail to parse are logged and skipped, use `get_raw_updates` to advance past them.
+    pub async fn get_updates(
+        &self,
+        offset: Option<i64>,
+        timeout: u32,
+        limit: u32,
+        allowed_updates: &[&str],
+    ) -> Result<Vec<Update>, TelegramError> {
+        let updates = self.get_raw_updates(offset, timeout, limit, allowed_updates).await?;
+        Ok(updates.into_iter().filter_map(parse_update).collect())
+    }
+
+    // Same as `get_updates` without parsing, so one update this version can't read
+    // doesn't fail the whole batch
+    pub async fn get_raw_updates(
+        &self,
+        offset: Option<i64>,
+        timeout: u32,
+        limit: u32,
+        allowed_updates: &[&str],
+    ) -> Result<Vec<serde_json::Value>, TelegramError> {
+        #[derive(Serialize)]
+        struct Params<'a> {
+            #[serde(skip_serializing_if = "Option::is_none")]
+            offset: Option<i64>,
+            timeout: u32,
+            limit: u32,
+            allowed_updates: &'a [&'a str],
+        }
+
+        self.call("getUpdates", &Params { offset, timeout, limit, allowed_updates }).await
+    }
+
+    pub async fn set_webhook(
+        &self,
+        url: &str,
+        secret_token: Option<&str>,
+        allowed_updates: &[&str],
+        drop_pending_updates: bool,
+    ) -> Result<bool, TelegramError> {
+        #[derive(Serialize)]
+        struct Params<'a> {
+            url: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            secret_token: Option<&'a str>,
+            allowed_updates: &'a [&'a str],
+            drop_pending_updates: bool,
+        }
+
+        self.call("setWebhook", &Params { url, secret_token, allowed_updates, drop_pending_updates }).await
+    }
+
+    pub async fn delete_webhook(&self, drop_pending_updates: bool) -> Result<bool, TelegramError> {
+        self.call("deleteWebhook", &serde_json::json!({
+            "drop_pending_updates": drop_pending_updates,
+        })).await
+    }
+
+    // Downloads a file by the `file_path` returned from getFile
+    pub async fn download_file(&self, file_path: &str) -> Result<Vec<u8>, TelegramError> {
+        let response = self.client
+            .get(self.file_url(file_path))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(TelegramError::Api {
+                error_code: status.as_u16() as i32,
+                description: format!("File download failed: {}", status),
+                parameters: ResponseParameters::default(),
+            });
+        }
+
+        Ok(response.bytes().await?.to_vec())
+    }
+}
+
+// Parses an update from `get_raw_updates`, None (logged) if it doesn't match `Update`
+pub fn parse_update(value: serde_json::Value) -> Option<Update> {
+    let update_id = value.get("update_id").and_then(|id| id.as_i64());
+    match serde_json::from_value(value) {
+        Ok(update) => Some(update),
+        Err(err) => {
+            eprintln!("Skipping Telegram update {:?} that failed to parse: {}", update_id, err);
+            None
+        }
+    }
+}
@@ -0,0 +1,652 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::{Interval, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    bot::{Bot, SendMessage, TelegramError},
+    format::{MAX_MESSAGE_LENGTH, split_html, split_text},
+    keyboard::ReplyMarkup,
+    types::ParseMode,
+};
+
+// Telegram allows about 30 messages per second overall and one per second to the same chat
+pub const GLOBAL_MESSAGES_PER_SEC: u32 = 30;
+pub const MAX_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Broadcast {
+    pub id: String,
+    pub text: String,
+    pub parse_mode: Option<ParseMode>,
+    pub reply_markup: Option<ReplyMarkup>,
+}
+
+impl Broadcast {
+    pub fn new(text: impl Into<String>) -> Self {
+        Broadcast {
+            id: crate::uuid::new().simple().to_string(),
+            text: text.into(),
+            parse_mode: None,
+            reply_markup: None,
+        }
+    }
+
+    pub fn html(text: impl Into<String>) -> Self {
+        Self::new(text).parse_mode(ParseMode::Html)
+    }
+
+    pub fn parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.parse_mode = Some(parse_mode);
+        self
+    }
+
+    pub fn reply_markup(mut self, reply_markup: impl Into<ReplyMarkup>) -> Self {
+        self.reply_markup = Some(reply_markup.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Sent,
+    Blocked,     // the user blocked the bot or it was kicked from the chat
+    Deactivated, // the account was deleted
+    NotFound,    // chat not found
+    Failed,      // gave up after MAX_ATTEMPTS
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Blocked => "blocked",
+            DeliveryStatus::Deactivated => "deactivated",
+            DeliveryStatus::NotFound => "notfound",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "pending" => Some(DeliveryStatus::Pending),
+            "sent" => Some(DeliveryStatus::Sent),
+            "blocked" => Some(DeliveryStatus::Blocked),
+            "deactivated" => Some(DeliveryStatus::Deactivated),
+            "notfound" => Some(DeliveryStatus::NotFound),
+            "failed" => Some(DeliveryStatus::Failed),
+            _ => None,
+        }
+    }
+
+    // Permanent failures, None means the error is worth retrying
+    pub fn from_error(err: &TelegramError) -> Option<Self> {
+        let TelegramError::Api { error_code, description, .. } = err else {
+            return None;
+        };
+        let description = description.to_lowercase();
+
+        match error_code {
+            403 if description.contains("deactivated") => Some(DeliveryStatus::Deactivated),
+            403 => Some(DeliveryStatus::Blocked),
+            400 if description.contains("chat not found") || description.contains("user not found") => Some(DeliveryStatus::NotFound),
+            // Bad markup or similar, retrying won't help
+            400 => Some(DeliveryStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+// What gets stored for a failed delivery: the API description or the kind of
+// transport error, never the request itself (its URL holds the bot token)
+fn delivery_error(err: &TelegramError) -> String {
+    match err {
+        TelegramError::Api { error_code, description, .. } => format!("{}: {}", error_code, description),
+        TelegramError::Http(err) if err.is_timeout() => "request timed out".to_string(),
+        TelegramError::Http(err) if err.is_connect() => "connection failed".to_string(),
+        TelegramError::Http(_) => "request failed".to_string(),
+        TelegramError::Invalid(_) => "unexpected response".to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recipient {
+    pub chat_id: i64,
+    pub attempts: u32,
+    // Parts of a long message already delivered, a retry resumes after them
+    pub sent_chunks: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Progress {
+    pub total: u64,
+    pub pending: u64,
+    pub sent: u64,
+    pub blocked: u64,
+    pub deactivated: u64,
+    pub not_found: u64,
+    pub failed: u64,
+}
+
+impl Progress {
+    fn add(&mut self, status: DeliveryStatus, count: u64) {
+        self.total += count;
+        match status {
+            DeliveryStatus::Pending => self.pending += count,
+            DeliveryStatus::Sent => self.sent += count,
+            DeliveryStatus::Blocked => self.blocked += count,
+            DeliveryStatus::Deactivated => self.deactivated += count,
+            DeliveryStatus::NotFound => self.not_found += count,
+            DeliveryStatus::Failed => self.failed += count,
+        }
+    }
+
+    fn remove(&mut self, status: DeliveryStatus) {
+        self.total -= 1;
+        match status {
+            DeliveryStatus::Pending => self.pending -= 1,
+            DeliveryStatus::Sent => self.sent -= 1,
+            DeliveryStatus::Blocked => self.blocked -= 1,
+            DeliveryStatus::Deactivated => self.deactivated -= 1,
+            DeliveryStatus::NotFound => self.not_found -= 1,
+            DeliveryStatus::Failed => self.failed -= 1,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pending == 0
+    }
+}
+
+pub trait BroadcastStore: Send + Sync + 'static {
+    // Saves the broadcast with every recipient pending, duplicates are ignored
+    fn create(&self, broadcast: &Broadcast, chat_ids: &[i64]) -> impl Future<Output = Result<(), String>> + Send;
+    fn get(&self, broadcast_id: &str) -> impl Future<Output = Result<Option<Broadcast>, String>> + Send;
+    fn pending(&self, broadcast_id: &str, limit: usize) -> impl Future<Output = Result<Vec<Recipient>, String>> + Send;
+    // Counts an attempt and stores the new status
+    fn mark(
+        &self,
+        broadcast_id: &str,
+        chat_id: i64,
+        status: DeliveryStatus,
+        error: Option<&str>,
+    ) -> impl Future<Output = Result<(), String>> + Send;
+    // Saved after every part of a long message so retries don't repeat it
+    fn mark_chunks(&self, broadcast_id: &str, chat_id: i64, sent_chunks: u32) -> impl Future<Output = Result<(), String>> + Send;
+    fn progress(&self, broadcast_id: &str) -> impl Future<Output = Result<Progress, String>> + Send;
+}
+
+struct Delivery {
+    // Position in the recipient list, keeps `pending` in insertion order
+    seq: u64,
+    status: DeliveryStatus,
+    attempts: u32,
+    sent_chunks: u32,
+    error: Option<String>,
+}
+
+struct BroadcastRecord {
+    broadcast: Broadcast,
+    deliveries: HashMap<i64, Delivery>,
+    order: Vec<i64>,
+    // seq -> chat_id of the recipients still pending
+    pending: BTreeMap<u64, i64>,
+    progress: Progress,
+}
+
+impl BroadcastRecord {
+    fn delivery(&mut self, broadcast_id: &str, chat_id: i64) -> Result<&mut Delivery, String> {
+        self.deliveries
+            .get_mut(&chat_id)
+            .ok_or_else(|| format!("Unknown recipient {} of broadcast {}", chat_id, broadcast_id))
+    }
+}
+
+// Keeps the queue in memory, it is lost on restart
+#[derive(Default)]
+pub struct MemoryBroadcastStore {
+    broadcasts: Mutex<HashMap<String, BroadcastRecord>>,
+}
+
+impl MemoryBroadcastStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Last error per failed chat, for inspection
+    pub fn errors(&self, broadcast_id: &str) -> Vec<(i64, String)> {
+        let broadcasts = self.broadcasts.lock().unwrap_or_else(|e| e.into_inner());
+        broadcasts
+            .get(broadcast_id)
+            .map(|record| {
+                record.order
+                    .iter()
+                    .filter_map(|chat_id| record.deliveries[chat_id].error.clone().map(|e| (*chat_id, e)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl BroadcastStore for MemoryBroadcastStore {
+    async fn create(&self, broadcast: &Broadcast, chat_ids: &[i64]) -> Result<(), String> {
+        let mut broadcasts = self.broadcasts.lock().unwrap_or_else(|e| e.into_inner());
+        let record = broadcasts
+            .entry(broadcast.id.clone())
+            .or_insert_with(|| BroadcastRecord {
+                broadcast: broadcast.clone(),
+                deliveries: HashMap::new(),
+                order: Vec::new(),
+                pending: BTreeMap::new(),
+                progress: Progress::default(),
+            });
+
+        for &chat_id in chat_ids {
+            if record.deliveries.contains_key(&chat_id) {
+                continue;
+            }
+            let seq = record.order.len() as u64;
+            record.deliveries.insert(chat_id, Delivery { seq, status: DeliveryStatus::Pending, attempts: 0, sent_chunks: 0, error: None });
+            record.order.push(chat_id);
+            record.pending.insert(seq, chat_id);
+            record.progress.add(DeliveryStatus::Pending, 1);
+        }
+        Ok(())
+    }
+
+    async fn get(&self, broadcast_id: &str) -> Result<Option<Broadcast>, String> {
+        let broadcasts = self.broadcasts.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(broadcasts.get(broadcast_id).map(|record| record.broadcast.clone()))
+    }
+
+    async fn pending(&self, broadcast_id: &str, limit: usize) -> Result<Vec<Recipient>, String> {
+        let broadcasts = self.broadcasts.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(record) = broadcasts.get(broadcast_id) else {
+            return Ok(Vec::new());
+        };
+
+        Ok(record.pending
+            .values()
+            .take(limit)
+            .map(|chat_id| {
+                let d = &record.deliveries[chat_id];
+                Recipient { chat_id: *chat_id, attempts: d.attempts, sent_chunks: d.sent_chunks }
+            })
+            .collect())
+    }
+
+    async fn mark(&self, broadcast_id: &str, chat_id: i64, status: DeliveryStatus, error: Option<&str>) -> Result<(), String> {
+        let mut broadcasts = self.broadcasts.lock().unwrap_or_else(|e| e.into_inner());
+        let record = broadcasts
+            .get_mut(broadcast_id)
+            .ok_or_else(|| format!("Unknown broadcast {}", broadcast_id))?;
+        let delivery = record.delivery(broadcast_id, chat_id)?;
+
+        let (seq, previous) = (delivery.seq, delivery.status);
+        delivery.status = status;
+        delivery.attempts += 1;
+        delivery.error = error.map(str::to_string);
+
+        record.progress.remove(previous);
+        record.progress.add(status, 1);
+        if status == DeliveryStatus::Pending {
+            record.pending.insert(seq, chat_id);
+        } else {
+            record.pending.remove(&seq);
+        }
+        Ok(())
+    }
+
+    async fn mark_chunks(&self, broadcast_id: &str, chat_id: i64, sent_chunks: u32) -> Result<(), String> {
+        let mut broadcasts = self.broadcasts.lock().unwrap_or_else(|e| e.into_inner());
+        let record = broadcasts
+            .get_mut(broadcast_id)
+            .ok_or_else(|| format!("Unknown broadcast {}", broadcast_id))?;
+
+        record.delivery(broadcast_id, chat_id)?.sent_chunks = sent_chunks;
+        Ok(())
+    }
+
+    async fn progress(&self, broadcast_id: &str) -> Result<Progress, String> {
+        let broadcasts = self.broadcasts.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(broadcasts.get(broadcast_id).map(|record| record.progress).unwrap_or_default())
+    }
+}
+
+// Survives restarts, `Broadcaster::run` picks up where it stopped
+#[cfg(feature = "db")]
+pub struct PgBroadcastStore {
+    pool: crate::db::Pool,
+}
+
+#[cfg(feature = "db")]
+impl PgBroadcastStore {
+    pub fn new(pool: crate::db::Pool) -> Self {
+        PgBroadcastStore { pool }
+    }
+
+    pub async fn create_table(&self) -> Result<(), crate::db::StdError> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS broadcasts (
+                id TEXT PRIMARY KEY,
+                message JSONB NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS broadcast_deliveries (
+                broadcast_id TEXT NOT NULL REFERENCES broadcasts(id) ON DELETE CASCADE,
+                chat_id BIGINT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'pending',
+                attempts INTEGER NOT NULL DEFAULT 0,
+                sent_chunks INTEGER NOT NULL DEFAULT 0,
+                error TEXT,
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                PRIMARY KEY (broadcast_id, chat_id)
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Tables created before parts were tracked
+        sqlx::query("ALTER TABLE broadcast_deliveries ADD COLUMN IF NOT EXISTS sent_chunks INTEGER NOT NULL DEFAULT 0")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "db")]
+impl BroadcastStore for PgBroadcastStore {
+    async fn create(&self, broadcast: &Broadcast, chat_ids: &[i64]) -> Result<(), String> {
+        let mut tx = self.pool.begin().await.map_err(|e| e.to_string())?;
+
+        sqlx::query("INSERT INTO broadcasts (id, message) VALUES ($1, $2) ON CONFLICT (id) DO NOTHING")
+            .bind(&broadcast.id)
+            .bind(sqlx::types::Json(broadcast))
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO broadcast_deliveries (broadcast_id, chat_id)
+            SELECT $1, chat_id FROM UNNEST($2::BIGINT[]) AS chat_id
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(&broadcast.id)
+        .bind(chat_ids)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        tx.commit().await.map_err(|e| e.to_string())
+    }
+
+    async fn get(&self, broadcast_id: &str) -> Result<Option<Broadcast>, String> {
+        let message = sqlx::query_scalar::<_, sqlx::types::Json<Broadcast>>("SELECT message FROM broadcasts WHERE id = $1")
+            .bind(broadcast_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(message.map(|json| json.0))
+    }
+
+    async fn pending(&self, broadcast_id: &str, limit: usize) -> Result<Vec<Recipient>, String> {
+        let rows = sqlx::query_as::<_, (i64, i32, i32)>(
+            r#"
+            SELECT chat_id, attempts, sent_chunks FROM broadcast_deliveries
+            WHERE broadcast_id = $1 AND status = 'pending'
+            ORDER BY chat_id
+            LIMIT $2
+            "#,
+        )
+        .bind(broadcast_id)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(chat_id, attempts, sent_chunks)| Recipient {
+                chat_id,
+                attempts: attempts.max(0) as u32,
+                sent_chunks: sent_chunks.max(0) as u32,
+            })
+            .collect())
+    }
+
+    async fn mark(&self, broadcast_id: &str, chat_id: i64, status: DeliveryStatus, error: Option<&str>) -> Result<(), String> {
+        sqlx::query(
+            r#"
+            UPDATE broadcast_deliveries
+            SET status = $3, error = $4, attempts = attempts + 1, updated_at = now()
+            WHERE broadcast_id = $1 AND chat_id = $2
+            "#,
+        )
+        .bind(broadcast_id)
+        .bind(chat_id)
+        .bind(status.as_str())
+        .bind(error)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    async fn mark_chunks(&self, broadcast_id: &str, chat_id: i64, sent_chunks: u32) -> Result<(), String> {
+        sqlx::query("UPDATE broadcast_deliveries SET sent_chunks = $3, updated_at = now() WHERE broadcast_id = $1 AND chat_id = $2")
+            .bind(broadcast_id)
+            .bind(chat_id)
+            .bind(sent_chunks as i32)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    async fn progress(&self, broadcast_id: &str) -> Result<Progress, String> {
+        let rows = sqlx::query_as::<_, (String, i64)>(
+            "SELECT status, COUNT(*) FROM broadcast_deliveries WHERE broadcast_id = $1 GROUP BY status",
+        )
+        .bind(broadcast_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let mut progress = Progress::default();
+        for (status, count) in rows {
+            if let Some(status) = DeliveryStatus::parse(&status) {
+                progress.add(status, count as u64);
+            }
+        }
+        Ok(progress)
+    }
+}
+
+// Sends broadcasts from a store while staying under Telegram's rate limits
+pub struct Broadcaster<S> {
+    bot: Bot,
+    store: S,
+    messages_per_sec: u32,
+    per_chat_interval: Duration,
+    max_attempts: u32,
+    batch_size: usize,
+}
+
+impl<S: BroadcastStore> Broadcaster<S> {
+    pub fn new(bot: Bot, store: S) -> Self {
+        Broadcaster {
+            bot,
+            store,
+            messages_per_sec: GLOBAL_MESSAGES_PER_SEC,
+            per_chat_interval: Duration::from_secs(1),
+            max_attempts: MAX_ATTEMPTS,
+            batch_size: 100,
+        }
+    }
+
+    // Paid broadcasts allow up to 1000 messages per second
+    pub fn messages_per_sec(mut self, messages_per_sec: u32) -> Self {
+        self.messages_per_sec = messages_per_sec.max(1);
+        self
+    }
+
+    // Gap between parts of a long message to the same chat
+    pub fn per_chat_interval(mut self, interval: Duration) -> Self {
+        self.per_chat_interval = interval;
+        self
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    // Queues `broadcast` for every chat, returns its id for `run` and `progress`
+    pub async fn enqueue(&self, broadcast: &Broadcast, chat_ids: &[i64]) -> Result<String, String> {
+        self.store.create(broadcast, chat_ids).await?;
+        Ok(broadcast.id.clone())
+    }
+
+    pub async fn progress(&self, broadcast_id: &str) -> Result<Progress, String> {
+        self.store.progress(broadcast_id).await
+    }
+
+    // Delivers every pending recipient until done or `cancel` fires, calling
+    // `on_progress` after each batch. Safe to call again to resume.
+    pub async fn run<F>(&self, broadcast_id: &str, cancel: CancellationToken, on_progress: F) -> Result<Progress, String>
+    where
+        F: Fn(&Progress),
+    {
+        let broadcast = self.store
+            .get(broadcast_id)
+            .await?
+            .ok_or_else(|| format!("Unknown broadcast {}", broadcast_id))?;
+
+        let chunks = match broadcast.parse_mode {
+            Some(ParseMode::Html) => split_html(&broadcast.text, MAX_MESSAGE_LENGTH),
+            None => split_text(&broadcast.text, MAX_MESSAGE_LENGTH),
+            Some(_) => vec![broadcast.text.clone()],
+        };
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(1) / self.messages_per_sec);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        'batches: loop {
+            let recipients = self.store.pending(broadcast_id, self.batch_size).await?;
+            if recipients.is_empty() {
+                break;
+            }
+
+            for recipient in recipients {
+                if cancel.is_cancelled() {
+                    break 'batches;
+                }
+
+                let (status, error) = match self.deliver(&broadcast, &chunks, recipient, &mut ticker, &cancel).await? {
+                    Some(Ok(())) => (DeliveryStatus::Sent, None),
+                    Some(Err(err)) => {
+                        let status = DeliveryStatus::from_error(&err).unwrap_or(
+                            if recipient.attempts + 1 >= self.max_attempts {
+                                DeliveryStatus::Failed
+                            } else {
+                                DeliveryStatus::Pending
+                            },
+                        );
+                        (status, Some(delivery_error(&err)))
+                    }
+                    None => break 'batches, // cancelled
+                };
+
+                self.store.mark(broadcast_id, recipient.chat_id, status, error.as_deref()).await?;
+            }
+
+            on_progress(&self.store.progress(broadcast_id).await?);
+        }
+
+        self.store.progress(broadcast_id).await
+    }
+
+    // Sends the parts the recipient hasn't got yet, saving progress after each.
+    // Ok(None) when cancelled while waiting.
+    async fn deliver(
+        &self,
+        broadcast: &Broadcast,
+        chunks: &[String],
+        recipient: Recipient,
+        ticker: &mut Interval,
+        cancel: &CancellationToken,
+    ) -> Result<Option<Result<(), TelegramError>>, String> {
+        let chat_id = recipient.chat_id;
+        let first = recipient.sent_chunks as usize;
+        let last = chunks.len().saturating_sub(1);
+
+        for (i, text) in chunks.iter().enumerate().skip(first) {
+            if i > first && !sleep_or_cancel(self.per_chat_interval, cancel).await {
+                return Ok(None);
+            }
+
+            let mut message = SendMessage::new(chat_id, text.as_str());
+            message.parse_mode = broadcast.parse_mode;
+            if i == last {
+                message.reply_markup = broadcast.reply_markup.clone();
+            }
+
+            loop {
+                tokio::select! {
+                    _ = cancel.cancelled() => return Ok(None),
+                    _ = ticker.tick() => {}
+                }
+
+                match self.bot.send_message(&message).await {
+                    Ok(_) => {
+                        if i < last {
+                            self.store.mark_chunks(&broadcast.id, chat_id, i as u32 + 1).await?;
+                        }
+                        break;
+                    }
+                    // Flood control applies to the whole bot, so everything waits
+                    Err(err) if err.retry_after().is_some() => {
+                        let wait = Duration::from_secs(err.retry_after().unwrap_or(1).max(1) as u64);
+                        eprintln!("Broadcast {} hit flood control, waiting {:?}", broadcast.id, wait);
+                        if !sleep_or_cancel(wait, cancel).await {
+                            return Ok(None);
+                        }
+                    }
+                    Err(err) => return Ok(Some(Err(err))),
+                }
+            }
+        }
+
+        Ok(Some(Ok(())))
+    }
+}
+
+async fn sleep_or_cancel(duration: Duration, cancel: &CancellationToken) -> bool {
+    tokio::select! {
+        _ = cancel.cancelled() => false,
+        _ = tokio::time::sleep(duration) => true,
+    }
+}
@@ -0,0 +1,278 @@
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
+
+use super::{
+    bot::{Bot, TelegramError},
+    types::{CallbackQuery, ChatMemberUpdated, InlineQuery, Message, PreCheckoutQuery, SuccessfulPayment, Update},
+};
+
+pub type HandlerResult = Result<(), TelegramError>;
+
+type BoxFuture = Pin<Box<dyn Future<Output = HandlerResult> + Send>>;
+type Handler<T> = Arc<dyn Fn(Bot, T) -> BoxFuture + Send + Sync>;
+
+// Every update type of the Bot API. An empty `allowed_updates` list leaves out
+// chat_member and the reaction updates, so they have to be named.
+pub const ALL_UPDATES: &[&str] = &[
+    "message",
+    "edited_message",
+    "channel_post",
+    "edited_channel_post",
+    "business_connection",
+    "business_message",
+    "edited_business_message",
+    "deleted_business_messages",
+    "message_reaction",
+    "message_reaction_count",
+    "inline_query",
+    "chosen_inline_result",
+    "callback_query",
+    "shipping_query",
+    "pre_checkout_query",
+    "purchased_paid_media",
+    "poll",
+    "poll_answer",
+    "my_chat_member",
+    "chat_member",
+    "chat_join_request",
+    "chat_boost",
+    "removed_chat_boost",
+];
+
+// "/start@MyBot payload" -> name "start", bot_username Some("MyBot"), args "payload"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub name: String,
+    pub bot_username: Option<String>,
+    pub args: String,
+}
+
+impl Command {
+    pub fn parse(text: &str) -> Option<Command> {
+        let text = text.strip_prefix('/')?;
+        let (head, args) = match text.split_once(char::is_whitespace) {
+            Some((head, args)) => (head, args.trim()),
+            None => (text, ""),
+        };
+
+        let (name, bot_username) = match head.split_once('@') {
+            Some((name, username)) => (name, Some(username.to_string())),
+            None => (head, None),
+        };
+
+        if name.is_empty() {
+            return None;
+        }
+
+        Some(Command {
+            name: name.to_lowercase(),
+            bot_username,
+            args: args.to_string(),
+        })
+    }
+}
+
+// Routes updates to registered handlers, shared by the webhook receiver and long polling
+#[derive(Clone)]
+pub struct Dispatcher {
+    bot: Bot,
+    username: Option<String>,
+    commands: HashMap<String, Handler<(Message, Command)>>,
+    message: Option<Handler<Message>>,
+    callback_query: Option<Handler<CallbackQuery>>,
+    inline_query: Option<Handler<InlineQuery>>,
+    pre_checkout_query: Option<Handler<PreCheckoutQuery>>,
+    successful_payment: Option<Handler<(Message, SuccessfulPayment)>>,
+    chat_member: Option<Handler<ChatMemberUpdated>>,
+    fallback: Option<Handler<Update>>,
+}
+
+fn handler<T, F, Fut>(f: F) -> Handler<T>
+where
+    F: Fn(Bot, T) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HandlerResult> + Send + 'static,
+{
+    Arc::new(move |bot, value| Box::pin(f(bot, value)))
+}
+
+impl Dispatcher {
+    pub fn new(bot: Bot) -> Self {
+        Dispatcher {
+            bot,
+            username: None,
+            commands: HashMap::new(),
+            message: None,
+            callback_query: None,
+            inline_query: None,
+            pre_checkout_query: None,
+            successful_payment: None,
+            chat_member: None,
+            fallback: None,
+        }
+    }
+
+    pub fn bot(&self) -> &Bot {
+        &self.bot
+    }
+
+    // Commands addressed to another bot ("/start@OtherBot") are ignored in groups
+    pub fn username(mut self, username: impl Into<String>) -> Self {
+        self.username = Some(username.into().trim_start_matches('@').to_string());
+        self
+    }
+
+    pub fn command<F, Fut>(mut self, name: &str, f: F) -> Self
+    where
+        F: Fn(Bot, Message, Command) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        let name = name.trim_start_matches('/').to_lowercase();
+        self.commands.insert(name, handler(move |bot, (message, command)| f(bot, message, command)));
+        self
+    }
+
+    // Messages that aren't registered commands or payments
+    pub fn on_message<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(Bot, Message) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        self.message = Some(handler(f));
+        self
+    }
+
+    pub fn on_callback_query<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(Bot, CallbackQuery) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        self.callback_query = Some(handler(f));
+        self
+    }
+
+    pub fn on_inline_query<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(Bot, InlineQuery) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        self.inline_query = Some(handler(f));
+        self
+    }
+
+    pub fn on_pre_checkout_query<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(Bot, PreCheckoutQuery) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        self.pre_checkout_query = Some(handler(f));
+        self
+    }
+
+    pub fn on_successful_payment<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(Bot, Message, SuccessfulPayment) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        self.successful_payment = Some(handler(move |bot, (message, payment)| f(bot, message, payment)));
+        self
+    }
+
+    // Both `chat_member` and `my_chat_member` updates
+    pub fn on_chat_member<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(Bot, ChatMemberUpdated) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        self.chat_member = Some(handler(f));
+        self
+    }
+
+    // Updates no other handler took
+    pub fn fallback<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(Bot, Update) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        self.fallback = Some(handler(f));
+        self
+    }
+
+    // Update types to pass as `allowed_updates` to setWebhook / getUpdates,
+    // all of them when a fallback wants whatever no handler takes
+    pub fn allowed_updates(&self) -> Vec<&'static str> {
+        if self.fallback.is_some() {
+            return ALL_UPDATES.to_vec();
+        }
+
+        let mut allowed = Vec::new();
+        if !self.commands.is_empty() || self.message.is_some() || self.successful_payment.is_some() {
+            allowed.push("message");
+        }
+        if self.callback_query.is_some() {
+            allowed.push("callback_query");
+        }
+        if self.inline_query.is_some() {
+            allowed.push("inline_query");
+        }
+        if self.pre_checkout_query.is_some() {
+            allowed.push("pre_checkout_query");
+        }
+        if self.chat_member.is_some() {
+            allowed.extend(["chat_member", "my_chat_member"]);
+        }
+        allowed
+    }
+
+    pub async fn dispatch(&self, update: Update) {
+        let update_id = update.update_id;
+        if let Err(err) = self.route(update).await {
+            eprintln!("Telegram handler error (update {}): {}", update_id, err);
+        }
+    }
+
+    async fn route(&self, update: Update) -> HandlerResult {
+        let bot = self.bot.clone();
+
+        if let Some(message) = &update.message {
+            if let (Some(payment), Some(handler)) = (&message.successful_payment, &self.successful_payment) {
+                return handler(bot, (message.clone(), payment.clone())).await;
+            }
+
+            if let Some(command) = message.text.as_deref().and_then(Command::parse) {
+                let addressed_to_us = match (&command.bot_username, &self.username) {
+                    (Some(target), Some(ours)) => target.eq_ignore_ascii_case(ours),
+                    _ => true,
+                };
+                if let (true, Some(handler)) = (addressed_to_us, self.commands.get(&command.name)) {
+                    return handler(bot, (message.clone(), command)).await;
+                }
+            }
+
+            if let Some(handler) = &self.message {
+                return handler(bot, message.clone()).await;
+            }
+        }
+
+        if let (Some(query), Some(handler)) = (&update.callback_query, &self.callback_query) {
+            return handler(bot, query.clone()).await;
+        }
+
+        if let (Some(query), Some(handler)) = (&update.inline_query, &self.inline_query) {
+            return handler(bot, query.clone()).await;
+        }
+
+        if let (Some(query), Some(handler)) = (&update.pre_checkout_query, &self.pre_checkout_query) {
+            return handler(bot, query.clone()).await;
+        }
+
+        let member = update.chat_member.as_ref().or(update.my_chat_member.as_ref());
+        if let (Some(member), Some(handler)) = (member, &self.chat_member) {
+            return handler(bot, member.clone()).await;
+        }
+
+        if let Some(handler) = &self.fallback {
+            return handler(bot, update).await;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,349 @@
+use std::fmt::Write;
+
+use super::{
+    bot::{Bot, SendMessage, TelegramError},
+    types::{ChatId, Message, ParseMode},
+};
+
+// Telegram's limit for message text, counted in UTF-16 code units after entity parsing
+pub const MAX_MESSAGE_LENGTH: usize = 4096;
+pub const MAX_CAPTION_LENGTH: usize = 1024;
+
+// Escapes user text for parse_mode HTML (also safe inside attribute values)
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Builds parse_mode HTML messages, every piece of text passed in is escaped
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Html {
+    html: String,
+}
+
+impl Html {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(mut self, text: &str) -> Self {
+        self.html.push_str(&escape(text));
+        self
+    }
+
+    // Already formatted HTML, only for trusted strings
+    pub fn raw(mut self, html: &str) -> Self {
+        self.html.push_str(html);
+        self
+    }
+
+    pub fn line(mut self) -> Self {
+        self.html.push('\n');
+        self
+    }
+
+    pub fn bold(self, text: &str) -> Self {
+        self.tag("b", text)
+    }
+
+    pub fn italic(self, text: &str) -> Self {
+        self.tag("i", text)
+    }
+
+    pub fn underline(self, text: &str) -> Self {
+        self.tag("u", text)
+    }
+
+    pub fn strike(self, text: &str) -> Self {
+        self.tag("s", text)
+    }
+
+    pub fn spoiler(self, text: &str) -> Self {
+        self.tag("tg-spoiler", text)
+    }
+
+    pub fn code(self, text: &str) -> Self {
+        self.tag("code", text)
+    }
+
+    pub fn pre(mut self, text: &str, language: Option<&str>) -> Self {
+        match language {
+            Some(language) => {
+                let _ = write!(self.html, "<pre><code class=\"language-{}\">{}</code></pre>", escape(language), escape(text));
+            }
+            None => {
+                let _ = write!(self.html, "<pre>{}</pre>", escape(text));
+            }
+        }
+        self
+    }
+
+    pub fn link(mut self, text: &str, url: &str) -> Self {
+        let _ = write!(self.html, "<a href=\"{}\">{}</a>", escape(url), escape(text));
+        self
+    }
+
+    // Mentions a user by id, works even without a username
+    pub fn mention(self, text: &str, user_id: i64) -> Self {
+        self.link(text, &format!("tg://user?id={}", user_id))
+    }
+
+    pub fn blockquote(self, text: &str) -> Self {
+        self.tag("blockquote", text)
+    }
+
+    // Collapsed by default, expanded on tap
+    pub fn expandable_blockquote(mut self, text: &str) -> Self {
+        let _ = write!(self.html, "<blockquote expandable>{}</blockquote>", escape(text));
+        self
+    }
+
+    // Wraps already built HTML, e.g. `Html::new().wrap("b", Html::new().text("a").italic("b"))`
+    pub fn wrap(mut self, tag: &str, inner: Html) -> Self {
+        let _ = write!(self.html, "<{}>{}</{}>", tag, inner.html, tag);
+        self
+    }
+
+    fn tag(mut self, tag: &str, text: &str) -> Self {
+        let _ = write!(self.html, "<{}>{}</{}>", tag, escape(text), tag);
+        self
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.html
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.html.is_empty()
+    }
+
+    // Splits into messages that fit Telegram's limit
+    pub fn split(&self, limit: usize) -> Vec<String> {
+        split_html(&self.html, limit)
+    }
+}
+
+impl std::fmt::Display for Html {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.html)
+    }
+}
+
+impl SendMessage {
+    pub fn html(chat_id: impl Into<ChatId>, html: Html) -> Self {
+        SendMessage::new(chat_id, html).parse_mode(ParseMode::Html)
+    }
+}
+
+impl From<Html> for String {
+    fn from(html: Html) -> Self {
+        html.html
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind<'a> {
+    Open(&'a str),
+    Close(&'a str),
+    Visible { len: usize, break_char: Option<char> },
+}
+
+struct Token<'a> {
+    text: &'a str,
+    kind: TokenKind<'a>,
+}
+
+// Byte length of the entity at the start of `rest` (`&amp;`, `&#123;`, `&#x1F600;`)
+fn entity_len(rest: &str) -> Option<usize> {
+    let body = rest.strip_prefix('&')?;
+    let (digits, is_valid): (&str, fn(char) -> bool) = match body.strip_prefix('#') {
+        Some(numeric) => match numeric.strip_prefix(['x', 'X']) {
+            Some(hex) => (hex, |c| c.is_ascii_hexdigit()),
+            None => (numeric, |c| c.is_ascii_digit()),
+        },
+        None => (body, |c| c.is_ascii_alphanumeric()),
+    };
+
+    let (end, _) = digits.char_indices().take(10).find(|(_, c)| !is_valid(*c))?;
+    if end == 0 || !digits[end..].starts_with(';') {
+        return None;
+    }
+    Some(rest.len() - digits.len() + end + 1)
+}
+
+// Splits into tags, entities (&amp;) and single visible characters
+fn tokenize(text: &str, html: bool) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    // Once no '>' is left there are no more tags to look for
+    let mut tags_left = html;
+
+    while let Some(c) = rest.chars().next() {
+        let tag_end = if tags_left && c == '<' { rest.find('>') } else { None };
+        if c == '<' && tag_end.is_none() {
+            tags_left = false;
+        }
+
+        let (len, kind) = if let Some(gt) = tag_end {
+            let inner = &rest[1..gt];
+            let kind = match inner.strip_prefix('/') {
+                Some(name) => TokenKind::Close(name.trim()),
+                None => TokenKind::Open(inner.split_whitespace().next().unwrap_or_default()),
+            };
+            (gt + 1, kind)
+        } else if let Some(end) = html.then(|| entity_len(rest)).flatten() {
+            (end, TokenKind::Visible { len: 1, break_char: None })
+        } else {
+            let break_char = matches!(c, '\n' | ' ').then_some(c);
+            (c.len_utf8(), TokenKind::Visible { len: c.len_utf16(), break_char })
+        };
+
+        tokens.push(Token { text: &rest[..len], kind });
+        rest = &rest[len..];
+    }
+
+    tokens
+}
+
+// Splits parse_mode HTML text into chunks of at most `limit` visible characters,
+// preferring paragraph, line and word boundaries. Tags open at a cut are closed
+// at the end of the chunk and reopened at the start of the next one.
+pub fn split_html(html: &str, limit: usize) -> Vec<String> {
+    split(html, limit, true)
+}
+
+// Same as `split_html` for messages sent without a parse_mode
+pub fn split_text(text: &str, limit: usize) -> Vec<String> {
+    split(text, limit, false)
+}
+
+// (tag name, original open tag) for every tag open at a position
+type OpenTags<'a> = Vec<(&'a str, &'a str)>;
+
+fn split(text: &str, limit: usize, html: bool) -> Vec<String> {
+    let limit = limit.max(2);
+    let tokens = tokenize(text, html);
+    let mut chunks = Vec::new();
+
+    let mut start = 0;
+    let mut open: OpenTags = Vec::new();
+
+    while start < tokens.len() {
+        let mut stack = open.clone();
+        let mut visible = 0;
+        let mut best: Option<(usize, u8, OpenTags)> = None;
+        let mut i = start;
+        let mut previous_break = None;
+
+        while i < tokens.len() {
+            let token = &tokens[i];
+            match token.kind {
+                TokenKind::Visible { len, break_char } => {
+                    if visible + len > limit {
+                        break;
+                    }
+                    visible += len;
+
+                    // Paragraph break beats line break beats space
+                    let priority = match (break_char, previous_break) {
+                        (Some('\n'), Some('\n')) => 3,
+                        (Some('\n'), _) => 2,
+                        (Some(_), _) => 1,
+                        _ => 0,
+                    };
+                    previous_break = break_char;
+
+                    if priority > 0 && best.as_ref().is_none_or(|(_, p, _)| priority >= *p) {
+                        best = Some((i + 1, priority, stack.clone()));
+                    }
+                }
+                TokenKind::Open(name) => stack.push((name, token.text)),
+                TokenKind::Close(name) => {
+                    if let Some(index) = stack.iter().rposition(|(open, _)| *open == name) {
+                        stack.truncate(index);
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        let (end, end_stack) = match best {
+            Some((end, _, end_stack)) if i < tokens.len() => (end, end_stack),
+            _ => (i, stack),
+        };
+
+        let mut chunk = String::new();
+        for (_, open_tag) in &open {
+            chunk.push_str(open_tag);
+        }
+        for token in &tokens[start..end] {
+            chunk.push_str(token.text);
+        }
+        for (name, _) in end_stack.iter().rev() {
+            let _ = write!(chunk, "</{}>", name);
+        }
+
+        let has_text = tokens[start..end].iter().any(|t| matches!(t.kind, TokenKind::Visible { break_char: None, .. }));
+        if has_text {
+            chunks.push(chunk);
+        }
+
+        start = end;
+        open = end_stack;
+    }
+
+    chunks
+}
+
+impl Bot {
+    // Sends text over the limit as several messages, the reply markup goes on the last one
+    pub async fn send_long_message(&self, params: &SendMessage) -> Result<Vec<Message>, TelegramError> {
+        let chunks = match params.parse_mode {
+            Some(ParseMode::Html) => split_html(&params.text, MAX_MESSAGE_LENGTH),
+            None => split_text(&params.text, MAX_MESSAGE_LENGTH),
+            // Markdown entities can't be split safely, let Telegram reject it
+            Some(_) => vec![params.text.clone()],
+        };
+
+        if chunks.len() <= 1 {
+            return Ok(vec![self.send_message(params).await?]);
+        }
+
+        let last = chunks.len() - 1;
+        let mut sent = Vec::with_capacity(chunks.len());
+        for (i, text) in chunks.into_iter().enumerate() {
+            let message = SendMessage {
+                text,
+                reply_markup: if i == last { params.reply_markup.clone() } else { None },
+                ..params.clone()
+            };
+            sent.push(self.send_message(&message).await?);
+        }
+
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_html_keeps_multibyte_text_after_ampersand() {
+        let html = Html::new().text("&Привет 👋 & мир &amp; &#128075;");
+        assert_eq!(split_html(html.as_str(), 4096), vec![html.as_str().to_string()]);
+
+        let chunks = split_html("&Привет мир", 8);
+        assert_eq!(chunks.concat().replace(' ', ""), "&Приветмир");
+        assert!(tokenize("&Привет;", true).iter().all(|t| t.text.chars().count() == 1));
+    }
+}
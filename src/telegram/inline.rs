@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    InitData,
+    bot::{Bot, TelegramError},
+    keyboard::{InlineKeyboardMarkup, WebAppInfo},
+    types::ParseMode,
+};
+
+// Message sent when the user picks a result
+#[derive(Debug, Clone, Serialize)]
+pub struct InputMessageContent {
+    pub message_text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parse_mode: Option<ParseMode>,
+}
+
+impl InputMessageContent {
+    pub fn text(text: impl Into<String>) -> Self {
+        InputMessageContent { message_text: text.into(), parse_mode: None }
+    }
+
+    pub fn html(text: impl Into<String>) -> Self {
+        InputMessageContent { message_text: text.into(), parse_mode: Some(ParseMode::Html) }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InlineQueryResultArticle {
+    pub id: String, // 1-64 bytes, unique within the answer
+    pub title: String,
+    pub input_message_content: InputMessageContent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_markup: Option<InlineKeyboardMarkup>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_url: Option<String>,
+}
+
+impl InlineQueryResultArticle {
+    pub fn new(id: impl Into<String>, title: impl Into<String>, content: InputMessageContent) -> Self {
+        InlineQueryResultArticle {
+            id: id.into(),
+            title: title.into(),
+            input_message_content: content,
+            reply_markup: None,
+            url: None,
+            description: None,
+            thumbnail_url: None,
+        }
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    pub fn thumbnail(mut self, thumbnail_url: impl Into<String>) -> Self {
+        self.thumbnail_url = Some(thumbnail_url.into());
+        self
+    }
+
+    pub fn reply_markup(mut self, reply_markup: InlineKeyboardMarkup) -> Self {
+        self.reply_markup = Some(reply_markup);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InlineQueryResultPhoto {
+    pub id: String,
+    pub photo_url: String, // JPEG up to 5 MB
+    pub thumbnail_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub photo_width: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub photo_height: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parse_mode: Option<ParseMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_markup: Option<InlineKeyboardMarkup>,
+    // Sends this message instead of the photo
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_message_content: Option<InputMessageContent>,
+}
+
+impl InlineQueryResultPhoto {
+    pub fn new(id: impl Into<String>, photo_url: impl Into<String>, thumbnail_url: impl Into<String>) -> Self {
+        InlineQueryResultPhoto {
+            id: id.into(),
+            photo_url: photo_url.into(),
+            thumbnail_url: thumbnail_url.into(),
+            photo_width: None,
+            photo_height: None,
+            title: None,
+            description: None,
+            caption: None,
+            parse_mode: None,
+            reply_markup: None,
+            input_message_content: None,
+        }
+    }
+
+    pub fn size(mut self, width: i64, height: i64) -> Self {
+        self.photo_width = Some(width);
+        self.photo_height = Some(height);
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn caption(mut self, caption: impl Into<String>, parse_mode: Option<ParseMode>) -> Self {
+        self.caption = Some(caption.into());
+        self.parse_mode = parse_mode;
+        self
+    }
+
+    pub fn reply_markup(mut self, reply_markup: InlineKeyboardMarkup) -> Self {
+        self.reply_markup = Some(reply_markup);
+        self
+    }
+
+    pub fn content(mut self, content: InputMessageContent) -> Self {
+        self.input_message_content = Some(content);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum InlineQueryResult {
+    Article(InlineQueryResultArticle),
+    Photo(InlineQueryResultPhoto),
+}
+
+impl From<InlineQueryResultArticle> for InlineQueryResult {
+    fn from(result: InlineQueryResultArticle) -> Self {
+        InlineQueryResult::Article(result)
+    }
+}
+
+impl From<InlineQueryResultPhoto> for InlineQueryResult {
+    fn from(result: InlineQueryResultPhoto) -> Self {
+        InlineQueryResult::Photo(result)
+    }
+}
+
+// Button shown above the results, opening the Mini App or a /start chat
+#[derive(Debug, Clone, Serialize)]
+pub struct InlineQueryResultsButton {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web_app: Option<WebAppInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_parameter: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AnswerInlineQuery {
+    pub inline_query_id: String,
+    pub results: Vec<InlineQueryResult>, // up to 50
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_time: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_personal: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_offset: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub button: Option<InlineQueryResultsButton>,
+}
+
+impl AnswerInlineQuery {
+    pub fn new(inline_query_id: impl Into<String>) -> Self {
+        AnswerInlineQuery {
+            inline_query_id: inline_query_id.into(),
+            results: Vec::new(),
+            cache_time: None,
+            is_personal: None,
+            next_offset: None,
+            button: None,
+        }
+    }
+
+    pub fn result(mut self, result: impl Into<InlineQueryResult>) -> Self {
+        self.results.push(result.into());
+        self
+    }
+
+    pub fn results(mut self, results: impl IntoIterator<Item = InlineQueryResult>) -> Self {
+        self.results.extend(results);
+        self
+    }
+
+    // Seconds Telegram may cache the results, 300 by default
+    pub fn cache_time(mut self, seconds: i32) -> Self {
+        self.cache_time = Some(seconds);
+        self
+    }
+
+    // Results depend on the user, don't share the cache between users
+    pub fn personal(mut self) -> Self {
+        self.is_personal = Some(true);
+        self
+    }
+
+    // Sent back as the query offset when the user scrolls, empty for no more results
+    pub fn next_offset(mut self, offset: impl Into<String>) -> Self {
+        self.next_offset = Some(offset.into());
+        self
+    }
+
+    pub fn web_app_button(mut self, text: impl Into<String>, url: impl Into<String>) -> Self {
+        self.button = Some(InlineQueryResultsButton {
+            text: text.into(),
+            web_app: Some(WebAppInfo { url: url.into() }),
+            start_parameter: None,
+        });
+        self
+    }
+
+    pub fn start_button(mut self, text: impl Into<String>, start_parameter: impl Into<String>) -> Self {
+        self.button = Some(InlineQueryResultsButton {
+            text: text.into(),
+            web_app: None,
+            start_parameter: Some(start_parameter.into()),
+        });
+        self
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SentWebAppMessage {
+    pub inline_message_id: Option<String>,
+}
+
+impl Bot {
+    pub async fn answer_inline_query(&self, params: &AnswerInlineQuery) -> Result<bool, TelegramError> {
+        self.call("answerInlineQuery", params).await
+    }
+
+    // Sends a message on behalf of the user into the chat the Mini App was opened from
+    pub async fn answer_web_app_query(
+        &self,
+        web_app_query_id: &str,
+        result: impl Into<InlineQueryResult>,
+    ) -> Result<SentWebAppMessage, TelegramError> {
+        self.call("answerWebAppQuery", &serde_json::json!({
+            "web_app_query_id": web_app_query_id,
+            "result": result.into(),
+        })).await
+    }
+
+    // answerWebAppQuery with the query_id of validated initData
+    pub async fn answer_web_app(
+        &self,
+        init_data: &InitData,
+        result: impl Into<InlineQueryResult>,
+    ) -> Result<SentWebAppMessage, TelegramError> {
+        let query_id = init_data.query_id.as_deref().ok_or_else(|| {
+            TelegramError::Invalid("initData has no query_id, the Mini App wasn't opened from a keyboard button".to_string())
+        })?;
+        self.answer_web_app_query(query_id, result).await
+    }
+}
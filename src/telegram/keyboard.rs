@@ -0,0 +1,278 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    bot::{Bot, TelegramError},
+    types::{ChatId, Edited},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebAppInfo {
+    pub url: String, // HTTPS URL of the Mini App
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CopyTextButton {
+    pub text: String,
+}
+
+// Exactly one of the optional fields must be set, use the constructors
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InlineKeyboardButton {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callback_data: Option<String>, // 1-64 bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web_app: Option<WebAppInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub switch_inline_query: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub switch_inline_query_current_chat: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub copy_text: Option<CopyTextButton>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pay: Option<bool>, // must be the first button of an invoice keyboard
+}
+
+impl InlineKeyboardButton {
+    fn new(text: impl Into<String>) -> Self {
+        InlineKeyboardButton { text: text.into(), ..Default::default() }
+    }
+
+    pub fn url(text: impl Into<String>, url: impl Into<String>) -> Self {
+        InlineKeyboardButton { url: Some(url.into()), ..Self::new(text) }
+    }
+
+    pub fn callback(text: impl Into<String>, data: impl Into<String>) -> Self {
+        InlineKeyboardButton { callback_data: Some(data.into()), ..Self::new(text) }
+    }
+
+    // Opens the Mini App, e.g. "Open the App"
+    pub fn web_app(text: impl Into<String>, url: impl Into<String>) -> Self {
+        InlineKeyboardButton { web_app: Some(WebAppInfo { url: url.into() }), ..Self::new(text) }
+    }
+
+    // Lets the user pick a chat and inserts "@bot query" there
+    pub fn switch_inline_query(text: impl Into<String>, query: impl Into<String>) -> Self {
+        InlineKeyboardButton { switch_inline_query: Some(query.into()), ..Self::new(text) }
+    }
+
+    pub fn switch_inline_query_current_chat(text: impl Into<String>, query: impl Into<String>) -> Self {
+        InlineKeyboardButton { switch_inline_query_current_chat: Some(query.into()), ..Self::new(text) }
+    }
+
+    pub fn copy_text(text: impl Into<String>, copy: impl Into<String>) -> Self {
+        InlineKeyboardButton { copy_text: Some(CopyTextButton { text: copy.into() }), ..Self::new(text) }
+    }
+
+    pub fn pay(text: impl Into<String>) -> Self {
+        InlineKeyboardButton { pay: Some(true), ..Self::new(text) }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InlineKeyboardMarkup {
+    pub inline_keyboard: Vec<Vec<InlineKeyboardButton>>,
+}
+
+impl InlineKeyboardMarkup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Keyboard with a single button
+    pub fn single(button: InlineKeyboardButton) -> Self {
+        Self::new().row([button])
+    }
+
+    pub fn row(mut self, buttons: impl IntoIterator<Item = InlineKeyboardButton>) -> Self {
+        self.inline_keyboard.push(buttons.into_iter().collect());
+        self
+    }
+
+    // Appends to the last row, starting one if there is none
+    pub fn button(mut self, button: InlineKeyboardButton) -> Self {
+        match self.inline_keyboard.last_mut() {
+            Some(row) => row.push(button),
+            None => self.inline_keyboard.push(vec![button]),
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyboardButton {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_contact: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_location: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web_app: Option<WebAppInfo>,
+}
+
+impl KeyboardButton {
+    pub fn text(text: impl Into<String>) -> Self {
+        KeyboardButton { text: text.into(), ..Default::default() }
+    }
+
+    pub fn request_contact(text: impl Into<String>) -> Self {
+        KeyboardButton { request_contact: Some(true), ..Self::text(text) }
+    }
+
+    pub fn request_location(text: impl Into<String>) -> Self {
+        KeyboardButton { request_location: Some(true), ..Self::text(text) }
+    }
+
+    // Mini Apps opened from a reply keyboard can use Telegram.WebApp.sendData
+    pub fn web_app(text: impl Into<String>, url: impl Into<String>) -> Self {
+        KeyboardButton { web_app: Some(WebAppInfo { url: url.into() }), ..Self::text(text) }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplyKeyboardMarkup {
+    pub keyboard: Vec<Vec<KeyboardButton>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_persistent: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resize_keyboard: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub one_time_keyboard: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_field_placeholder: Option<String>,
+}
+
+impl ReplyKeyboardMarkup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn row(mut self, buttons: impl IntoIterator<Item = KeyboardButton>) -> Self {
+        self.keyboard.push(buttons.into_iter().collect());
+        self
+    }
+
+    pub fn button(mut self, button: KeyboardButton) -> Self {
+        match self.keyboard.last_mut() {
+            Some(row) => row.push(button),
+            None => self.keyboard.push(vec![button]),
+        }
+        self
+    }
+
+    pub fn persistent(mut self) -> Self {
+        self.is_persistent = Some(true);
+        self
+    }
+
+    pub fn resize(mut self) -> Self {
+        self.resize_keyboard = Some(true);
+        self
+    }
+
+    pub fn one_time(mut self) -> Self {
+        self.one_time_keyboard = Some(true);
+        self
+    }
+
+    pub fn placeholder(mut self, placeholder: impl Into<String>) -> Self {
+        self.input_field_placeholder = Some(placeholder.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplyKeyboardRemove {
+    pub remove_keyboard: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForceReply {
+    pub force_reply: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_field_placeholder: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ReplyMarkup {
+    Inline(InlineKeyboardMarkup),
+    Reply(ReplyKeyboardMarkup),
+    Remove(ReplyKeyboardRemove),
+    ForceReply(ForceReply),
+}
+
+impl ReplyMarkup {
+    pub fn remove() -> Self {
+        ReplyMarkup::Remove(ReplyKeyboardRemove { remove_keyboard: true })
+    }
+
+    pub fn force_reply(placeholder: Option<String>) -> Self {
+        ReplyMarkup::ForceReply(ForceReply { force_reply: true, input_field_placeholder: placeholder })
+    }
+}
+
+impl From<InlineKeyboardMarkup> for ReplyMarkup {
+    fn from(markup: InlineKeyboardMarkup) -> Self {
+        ReplyMarkup::Inline(markup)
+    }
+}
+
+impl From<ReplyKeyboardMarkup> for ReplyMarkup {
+    fn from(markup: ReplyKeyboardMarkup) -> Self {
+        ReplyMarkup::Reply(markup)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MenuButton {
+    Commands,
+    WebApp { text: String, web_app: WebAppInfo },
+    Default,
+}
+
+impl MenuButton {
+    pub fn web_app(text: impl Into<String>, url: impl Into<String>) -> Self {
+        MenuButton::WebApp { text: text.into(), web_app: WebAppInfo { url: url.into() } }
+    }
+}
+
+impl Bot {
+    // Without a chat_id the default menu button for all private chats is changed
+    pub async fn set_chat_menu_button(&self, chat_id: Option<i64>, menu_button: &MenuButton) -> Result<bool, TelegramError> {
+        #[derive(Serialize)]
+        struct Params<'a> {
+            #[serde(skip_serializing_if = "Option::is_none")]
+            chat_id: Option<i64>,
+            menu_button: &'a MenuButton,
+        }
+
+        self.call("setChatMenuButton", &Params { chat_id, menu_button }).await
+    }
+
+    pub async fn get_chat_menu_button(&self, chat_id: Option<i64>) -> Result<MenuButton, TelegramError> {
+        #[derive(Serialize)]
+        struct Params {
+            #[serde(skip_serializing_if = "Option::is_none")]
+            chat_id: Option<i64>,
+        }
+
+        self.call("getChatMenuButton", &Params { chat_id }).await
+    }
+
+    pub async fn edit_message_reply_markup(
+        &self,
+        chat_id: impl Into<ChatId>,
+        message_id: i64,
+        reply_markup: Option<InlineKeyboardMarkup>,
+    ) -> Result<Edited, TelegramError> {
+        self.call("editMessageReplyMarkup", &serde_json::json!({
+            "chat_id": chat_id.into(),
+            "message_id": message_id,
+            "reply_markup": reply_markup.unwrap_or_default(),
+        })).await
+    }
+}
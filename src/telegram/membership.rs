@@ -0,0 +1,158 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tokio::task::JoinSet;
+
+use super::{
+    bot::{Bot, TelegramError},
+    types::{ChatId, ChatMember, ChatMemberStatus},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Membership {
+    Owner,
+    Admin,
+    Member,
+    Restricted, // still in the chat with limited rights
+    Left,       // never joined, left, or restricted after leaving
+    Banned,
+}
+
+impl Membership {
+    pub fn is_joined(&self) -> bool {
+        matches!(self, Membership::Owner | Membership::Admin | Membership::Member | Membership::Restricted)
+    }
+}
+
+impl From<&ChatMember> for Membership {
+    fn from(member: &ChatMember) -> Self {
+        match member.status {
+            ChatMemberStatus::Creator => Membership::Owner,
+            ChatMemberStatus::Administrator => Membership::Admin,
+            ChatMemberStatus::Member => Membership::Member,
+            ChatMemberStatus::Restricted if member.is_member.unwrap_or(false) => Membership::Restricted,
+            ChatMemberStatus::Restricted | ChatMemberStatus::Left => Membership::Left,
+            ChatMemberStatus::Kicked => Membership::Banned,
+        }
+    }
+}
+
+impl Bot {
+    // The bot has to be an admin of channels to see their members
+    pub async fn membership(&self, chat_id: impl Into<ChatId>, user_id: i64) -> Result<Membership, TelegramError> {
+        match self.get_chat_member(chat_id, user_id).await {
+            Ok(member) => Ok(Membership::from(&member)),
+            // Users that never talked to the chat aren't "found"
+            Err(TelegramError::Api { error_code: 400, description, .. })
+                if description.to_lowercase().contains("user not found") =>
+            {
+                Ok(Membership::Left)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+// Checks users against several chats at once, remembering confirmed memberships for `ttl`.
+// Negative results aren't cached since users usually retry right after joining.
+pub struct MembershipChecker {
+    bot: Bot,
+    ttl: Duration,
+    joined: Mutex<HashMap<(String, i64), Instant>>,
+}
+
+impl MembershipChecker {
+    pub fn new(bot: Bot, ttl: Duration) -> Self {
+        MembershipChecker { bot, ttl, joined: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn bot(&self) -> &Bot {
+        &self.bot
+    }
+
+    fn is_cached(&self, chat: &str, user_id: i64) -> bool {
+        let mut joined = self.joined.lock().unwrap_or_else(|e| e.into_inner());
+        match joined.get(&(chat.to_string(), user_id)) {
+            Some(at) if at.elapsed() < self.ttl => true,
+            Some(_) => {
+                joined.remove(&(chat.to_string(), user_id));
+                false
+            }
+            None => false,
+        }
+    }
+
+    // Membership per chat, in the order of `chats`; cached chats report Member
+    pub async fn check(&self, user_id: i64, chats: &[ChatId]) -> Result<Vec<(ChatId, Membership)>, TelegramError> {
+        let mut results: Vec<Option<Membership>> = vec![None; chats.len()];
+        let mut requests = JoinSet::new();
+
+        for (i, chat) in chats.iter().enumerate() {
+            if self.is_cached(&chat.to_string(), user_id) {
+                results[i] = Some(Membership::Member);
+                continue;
+            }
+
+            let bot = self.bot.clone();
+            let chat = chat.clone();
+            requests.spawn(async move { (i, bot.membership(chat, user_id).await) });
+        }
+
+        while let Some(joined) = requests.join_next().await {
+            let Ok((i, membership)) = joined else {
+                continue;
+            };
+            let membership = membership?;
+
+            if membership.is_joined() {
+                let mut cache = self.joined.lock().unwrap_or_else(|e| e.into_inner());
+                cache.insert((chats[i].to_string(), user_id), Instant::now());
+            }
+            results[i] = Some(membership);
+        }
+
+        Ok(chats
+            .iter()
+            .cloned()
+            .zip(results)
+            .map(|(chat, membership)| (chat, membership.unwrap_or(Membership::Left)))
+            .collect())
+    }
+
+    // True only if the user is in every chat
+    pub async fn joined_all(&self, user_id: i64, chats: &[ChatId]) -> Result<bool, TelegramError> {
+        let memberships = self.check(user_id, chats).await?;
+        Ok(memberships.iter().all(|(_, membership)| membership.is_joined()))
+    }
+
+    // Completes a Social/Partner task: records `action_type` in tasker_actions once the
+    // user joined every chat. Returns whether the task is (or already was) completed.
+    #[cfg(feature = "tasker")]
+    pub async fn complete_task(
+        &self,
+        pool: &sqlx::PgPool,
+        action_type: &str,
+        user_id: i64,
+        chats: &[ChatId],
+    ) -> Result<bool, String> {
+        let done = crate::tasker::try_check_special_task(pool, action_type, user_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        if done > 0 {
+            return Ok(true);
+        }
+
+        if !self.joined_all(user_id, chats).await.map_err(|e| e.to_string())? {
+            return Ok(false);
+        }
+
+        // A concurrent call may have recorded it since the check, either way it's done
+        crate::tasker::record_action_once(pool, action_type, user_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+}
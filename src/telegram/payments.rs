@@ -0,0 +1,491 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    bot::{Bot, TelegramError},
+    dispatch::Dispatcher,
+    keyboard::InlineKeyboardMarkup,
+    types::{ChatId, Message, PreCheckoutQuery, SuccessfulPayment},
+};
+
+// Telegram Stars currency code
+pub const XTR: &str = "XTR";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LabeledPrice {
+    pub label: String,
+    pub amount: i64, // in Stars
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Invoice {
+    pub title: String,             // 1-32 characters
+    pub description: String,       // 1-255 characters
+    pub payload: String,           // 1-128 bytes, not shown to the user
+    pub currency: String,
+    pub prices: Vec<LabeledPrice>, // exactly one price for Stars
+    // Empty for payments in Telegram Stars
+    pub provider_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscription_period: Option<i64>, // 2592000 (30 days) for subscriptions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub photo_url: Option<String>,
+    // Only used by sendInvoice, invoice links can't carry a keyboard
+    #[serde(skip)]
+    pub reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+impl Invoice {
+    pub fn stars(title: impl Into<String>, description: impl Into<String>, payload: impl Into<String>, amount: i64) -> Self {
+        let title = title.into();
+        Invoice {
+            prices: vec![LabeledPrice { label: title.clone(), amount }],
+            title,
+            description: description.into(),
+            payload: payload.into(),
+            currency: XTR.to_string(),
+            provider_token: String::new(),
+            subscription_period: None,
+            photo_url: None,
+            reply_markup: None,
+        }
+    }
+
+    // Monthly subscription, the only period Telegram supports
+    pub fn monthly(mut self) -> Self {
+        self.subscription_period = Some(30 * 24 * 60 * 60);
+        self
+    }
+
+    pub fn photo_url(mut self, url: impl Into<String>) -> Self {
+        self.photo_url = Some(url.into());
+        self
+    }
+
+    // The first button must be `InlineKeyboardButton::pay`
+    pub fn reply_markup(mut self, reply_markup: InlineKeyboardMarkup) -> Self {
+        self.reply_markup = Some(reply_markup);
+        self
+    }
+}
+
+impl Bot {
+    pub async fn create_invoice_link(&self, invoice: &Invoice) -> Result<String, TelegramError> {
+        self.call("createInvoiceLink", invoice).await
+    }
+
+    pub async fn send_invoice(&self, chat_id: impl Into<ChatId>, invoice: &Invoice) -> Result<Message, TelegramError> {
+        let mut params = serde_json::to_value(invoice)
+            .map_err(|e| TelegramError::Invalid(e.to_string()))?;
+        params["chat_id"] = serde_json::to_value(chat_id.into())
+            .map_err(|e| TelegramError::Invalid(e.to_string()))?;
+        if let Some(reply_markup) = &invoice.reply_markup {
+            params["reply_markup"] = serde_json::to_value(reply_markup)
+                .map_err(|e| TelegramError::Invalid(e.to_string()))?;
+        }
+
+        self.call("sendInvoice", &params).await
+    }
+
+    // Must be answered within 10 seconds of the pre_checkout_query
+    pub async fn answer_pre_checkout_query(
+        &self,
+        pre_checkout_query_id: &str,
+        result: Result<(), String>,
+    ) -> Result<bool, TelegramError> {
+        let params = match result {
+            Ok(()) => serde_json::json!({
+                "pre_checkout_query_id": pre_checkout_query_id,
+                "ok": true,
+            }),
+            Err(error_message) => serde_json::json!({
+                "pre_checkout_query_id": pre_checkout_query_id,
+                "ok": false,
+                "error_message": error_message,
+            }),
+        };
+
+        self.call("answerPreCheckoutQuery", &params).await
+    }
+
+    pub async fn refund_star_payment(&self, user_id: i64, telegram_payment_charge_id: &str) -> Result<bool, TelegramError> {
+        self.call("refundStarPayment", &serde_json::json!({
+            "user_id": user_id,
+            "telegram_payment_charge_id": telegram_payment_charge_id,
+        })).await
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StarsPayment {
+    pub charge_id: String, // telegram_payment_charge_id, unique per payment
+    pub user_id: i64,
+    pub amount: i64,
+    pub payload: String,
+}
+
+impl StarsPayment {
+    pub fn from_message(message: &Message, payment: &SuccessfulPayment) -> Self {
+        StarsPayment {
+            charge_id: payment.telegram_payment_charge_id.clone(),
+            user_id: message.from.as_ref().map(|user| user.id).unwrap_or(message.chat.id),
+            amount: payment.total_amount,
+            payload: payment.invoice_payload.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredPayment {
+    pub payment: StarsPayment,
+    pub credited: bool,
+    pub refunded: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordOutcome {
+    New,
+    Duplicate { credited: bool },
+}
+
+// Remembers every successful payment so redelivered updates don't credit twice
+pub trait PaymentStore: Send + Sync + 'static {
+    fn record(&self, payment: &StarsPayment) -> impl Future<Output = Result<RecordOutcome, String>> + Send;
+    fn mark_credited(&self, charge_id: &str) -> impl Future<Output = Result<(), String>> + Send;
+    // false if the payment is unknown or already refunded
+    fn mark_refunded(&self, charge_id: &str) -> impl Future<Output = Result<bool, String>> + Send;
+    fn get(&self, charge_id: &str) -> impl Future<Output = Result<Option<StoredPayment>, String>> + Send;
+    // Recorded but not credited yet (the credit failed) and not refunded
+    fn uncredited(&self) -> impl Future<Output = Result<Vec<StarsPayment>, String>> + Send;
+}
+
+#[derive(Default)]
+pub struct MemoryPaymentStore {
+    payments: Mutex<HashMap<String, StoredPayment>>,
+}
+
+impl MemoryPaymentStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl PaymentStore for MemoryPaymentStore {
+    async fn record(&self, payment: &StarsPayment) -> Result<RecordOutcome, String> {
+        let mut payments = self.payments.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(record) = payments.get(&payment.charge_id) {
+            return Ok(RecordOutcome::Duplicate { credited: record.credited });
+        }
+        payments.insert(
+            payment.charge_id.clone(),
+            StoredPayment { payment: payment.clone(), credited: false, refunded: false },
+        );
+        Ok(RecordOutcome::New)
+    }
+
+    async fn mark_credited(&self, charge_id: &str) -> Result<(), String> {
+        let mut payments = self.payments.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(record) = payments.get_mut(charge_id) {
+            record.credited = true;
+        }
+        Ok(())
+    }
+
+    async fn mark_refunded(&self, charge_id: &str) -> Result<bool, String> {
+        let mut payments = self.payments.lock().unwrap_or_else(|e| e.into_inner());
+        match payments.get_mut(charge_id) {
+            Some(record) if !record.refunded => {
+                record.refunded = true;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn get(&self, charge_id: &str) -> Result<Option<StoredPayment>, String> {
+        let payments = self.payments.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(payments.get(charge_id).cloned())
+    }
+
+    async fn uncredited(&self) -> Result<Vec<StarsPayment>, String> {
+        let payments = self.payments.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(payments
+            .values()
+            .filter(|record| !record.credited && !record.refunded)
+            .map(|record| record.payment.clone())
+            .collect())
+    }
+}
+
+#[cfg(feature = "db")]
+pub struct PgPaymentStore {
+    pool: crate::db::Pool,
+}
+
+#[cfg(feature = "db")]
+impl PgPaymentStore {
+    pub fn new(pool: crate::db::Pool) -> Self {
+        PgPaymentStore { pool }
+    }
+
+    pub async fn create_table(&self) -> Result<(), crate::db::StdError> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS stars_payments (
+                charge_id TEXT PRIMARY KEY,
+                user_id BIGINT NOT NULL,
+                amount BIGINT NOT NULL,
+                payload TEXT NOT NULL,
+                credited BOOLEAN NOT NULL DEFAULT FALSE,
+                refunded BOOLEAN NOT NULL DEFAULT FALSE,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "db")]
+impl PaymentStore for PgPaymentStore {
+    async fn record(&self, payment: &StarsPayment) -> Result<RecordOutcome, String> {
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO stars_payments (charge_id, user_id, amount, payload)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (charge_id) DO NOTHING
+            "#,
+        )
+        .bind(&payment.charge_id)
+        .bind(payment.user_id)
+        .bind(payment.amount)
+        .bind(&payment.payload)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .rows_affected() == 1;
+
+        if inserted {
+            return Ok(RecordOutcome::New);
+        }
+
+        let credited = sqlx::query_scalar::<_, bool>("SELECT credited FROM stars_payments WHERE charge_id = $1")
+            .bind(&payment.charge_id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(RecordOutcome::Duplicate { credited })
+    }
+
+    async fn mark_credited(&self, charge_id: &str) -> Result<(), String> {
+        sqlx::query("UPDATE stars_payments SET credited = TRUE WHERE charge_id = $1")
+            .bind(charge_id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    async fn mark_refunded(&self, charge_id: &str) -> Result<bool, String> {
+        let result = sqlx::query("UPDATE stars_payments SET refunded = TRUE WHERE charge_id = $1 AND NOT refunded")
+            .bind(charge_id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn get(&self, charge_id: &str) -> Result<Option<StoredPayment>, String> {
+        let row = sqlx::query_as::<_, (String, i64, i64, String, bool, bool)>(
+            "SELECT charge_id, user_id, amount, payload, credited, refunded FROM stars_payments WHERE charge_id = $1",
+        )
+        .bind(charge_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        Ok(row.map(|(charge_id, user_id, amount, payload, credited, refunded)| StoredPayment {
+            payment: StarsPayment { charge_id, user_id, amount, payload },
+            credited,
+            refunded,
+        }))
+    }
+
+    async fn uncredited(&self) -> Result<Vec<StarsPayment>, String> {
+        let rows = sqlx::query_as::<_, (String, i64, i64, String)>(
+            r#"
+            SELECT charge_id, user_id, amount, payload FROM stars_payments
+            WHERE NOT credited AND NOT refunded
+            ORDER BY created_at
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(charge_id, user_id, amount, payload)| StarsPayment { charge_id, user_id, amount, payload })
+            .collect())
+    }
+}
+
+pub struct StarsPayments<S> {
+    store: S,
+    #[cfg(feature = "currency")]
+    credit: Option<crate::balance::BalanceClient>,
+}
+
+impl<S: PaymentStore> StarsPayments<S> {
+    pub fn new(store: S) -> Self {
+        StarsPayments {
+            store,
+            #[cfg(feature = "currency")]
+            credit: None,
+        }
+    }
+
+    #[cfg(feature = "currency")]
+    // Credits purchased Stars to the balance service
+    pub fn credit(mut self, balance: crate::balance::BalanceClient) -> Self {
+        self.credit = Some(balance);
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    // Records a successful payment once, crediting it if a balance client is set.
+    // Returns false for duplicates that were already handled. A payment whose credit
+    // fails stays uncredited in the store for `retry_uncredited`.
+    pub async fn record(&self, payment: &StarsPayment) -> Result<bool, String> {
+        let outcome = self.store.record(payment).await?;
+        if outcome == (RecordOutcome::Duplicate { credited: true }) {
+            return Ok(false);
+        }
+
+        #[cfg(feature = "currency")]
+        if let Some(balance) = &self.credit {
+            self.credit_payment(balance, payment).await?;
+        }
+
+        Ok(outcome == RecordOutcome::New)
+    }
+
+    // Credits payments whose credit failed after Telegram already considered them
+    // delivered, meant to run on a timer. Returns how many were credited.
+    #[cfg(feature = "currency")]
+    pub async fn retry_uncredited(&self) -> Result<usize, String> {
+        let Some(balance) = &self.credit else {
+            return Ok(0);
+        };
+
+        let mut credited = 0;
+        for payment in self.store.uncredited().await? {
+            match self.credit_payment(balance, &payment).await {
+                Ok(()) => credited += 1,
+                Err(err) => eprintln!("Failed to credit Stars payment {}: {}", payment.charge_id, err),
+            }
+        }
+        Ok(credited)
+    }
+
+    #[cfg(feature = "currency")]
+    async fn credit_payment(&self, balance: &crate::balance::BalanceClient, payment: &StarsPayment) -> Result<(), String> {
+        // Keyed by the charge so a retry after a lost response isn't credited twice
+        let amount = rust_decimal::Decimal::from(payment.amount);
+        let key = format!("stars:{}", payment.charge_id);
+        let credited = balance
+            .add(&crate::currency::Currency::STARS, &amount, payment.user_id, true, Some(&key))
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if let crate::balance::BalanceOutcome::Rejected { status, reason, .. } = credited {
+            return Err(format!("Stars credit rejected ({}): {}", status, reason));
+        }
+
+        self.store.mark_credited(&payment.charge_id).await
+    }
+
+    // Refunds the Stars to the user who paid, as recorded in the store. With a
+    // balance client the credit is reversed first, so a user who already spent
+    // the Stars can't be refunded. Returns false if it was already refunded.
+    pub async fn refund(&self, bot: &Bot, charge_id: &str) -> Result<bool, String> {
+        let Some(stored) = self.store.get(charge_id).await? else {
+            return Err(format!("Unknown Stars payment {}", charge_id));
+        };
+        if stored.refunded {
+            return Ok(false);
+        }
+
+        #[cfg(feature = "currency")]
+        if let Some(balance) = &self.credit {
+            // Credits that went through but were never marked are reversed too,
+            // both calls are idempotent so a failed refund can be retried
+            self.credit_payment(balance, &stored.payment).await?;
+
+            let amount = rust_decimal::Decimal::from(stored.payment.amount);
+            let key = format!("stars-refund:{}", charge_id);
+            let reversed = balance
+                .sub(&crate::currency::Currency::STARS, &amount, stored.payment.user_id, Some(&key))
+                .await
+                .map_err(|e| e.to_string())?;
+
+            if let crate::balance::BalanceOutcome::Rejected { status, reason, .. } = reversed {
+                return Err(format!("Stars refund rejected ({}): {}", status, reason));
+            }
+        }
+
+        bot.refund_star_payment(stored.payment.user_id, charge_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        self.store.mark_refunded(charge_id).await
+    }
+}
+
+impl Dispatcher {
+    // Answers Stars pre-checkout queries with `validate` and records successful payments.
+    // Keep a clone of `payments` for refunds and `retry_uncredited`.
+    pub fn stars<S, V, Fut>(self, payments: Arc<StarsPayments<S>>, validate: V) -> Self
+    where
+        S: PaymentStore,
+        V: Fn(PreCheckoutQuery) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let validate = Arc::new(validate);
+
+        self
+            .on_pre_checkout_query(move |bot, query| {
+                let validate = validate.clone();
+                async move {
+                    let result = if query.currency != XTR {
+                        Err("Only Telegram Stars are accepted".to_string())
+                    } else {
+                        validate(query.clone()).await
+                    };
+                    bot.answer_pre_checkout_query(&query.id, result).await?;
+                    Ok(())
+                }
+            })
+            .on_successful_payment(move |_bot, message, payment| {
+                let payments = payments.clone();
+                async move {
+                    let payment = StarsPayment::from_message(&message, &payment);
+                    if let Err(err) = payments.record(&payment).await {
+                        // Left uncredited in the store, `retry_uncredited` picks it up
+                        eprintln!("Failed to record Stars payment {}: {}", payment.charge_id, err);
+                    }
+                    Ok(())
+                }
+            })
+    }
+}
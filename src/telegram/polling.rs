@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use super::{bot::parse_update, dispatch::Dispatcher};
+
+// getUpdates loop for local development and deployments without a public URL,
+// feeding the same Dispatcher as the webhook receiver
+pub struct Polling {
+    dispatcher: Dispatcher,
+    timeout_secs: u32,
+    limit: u32,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    delete_webhook: bool,
+}
+
+impl Polling {
+    pub fn new(dispatcher: Dispatcher) -> Self {
+        Polling {
+            dispatcher,
+            timeout_secs: 30,
+            limit: 100,
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            delete_webhook: true,
+        }
+    }
+
+    pub fn timeout(mut self, seconds: u32) -> Self {
+        self.timeout_secs = seconds;
+        self
+    }
+
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = limit.clamp(1, 100);
+        self
+    }
+
+    pub fn backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.min_backoff = min;
+        self.max_backoff = max.max(min);
+        self
+    }
+
+    // getUpdates doesn't work while a webhook is set, it is removed on start by default
+    pub fn keep_webhook(mut self) -> Self {
+        self.delete_webhook = false;
+        self
+    }
+
+    // Runs until `cancel` fires, then waits for in-flight handlers
+    pub async fn run(self, cancel: CancellationToken) {
+        let bot = self.dispatcher.bot().clone();
+        let allowed_updates = self.dispatcher.allowed_updates();
+
+        if self.delete_webhook
+            && let Err(err) = bot.delete_webhook(false).await
+        {
+            eprintln!("Failed to delete Telegram webhook: {}", err);
+        }
+
+        let mut offset: Option<i64> = None;
+        let mut backoff = self.min_backoff;
+        let mut handlers = JoinSet::new();
+
+        loop {
+            let result = tokio::select! {
+                _ = cancel.cancelled() => break,
+                result = bot.get_raw_updates(offset, self.timeout_secs, self.limit, &allowed_updates) => result,
+            };
+
+            // Reap finished handlers so the set doesn't grow
+            while handlers.try_join_next().is_some() {}
+
+            let updates = match result {
+                Ok(updates) => updates,
+                Err(err) => {
+                    // Honor retry_after on 429, otherwise back off exponentially
+                    let wait = match err.retry_after() {
+                        Some(seconds) => Duration::from_secs(seconds.max(1) as u64),
+                        None => {
+                            let wait = backoff;
+                            backoff = (backoff * 2).min(self.max_backoff);
+                            wait
+                        }
+                    };
+                    eprintln!("Telegram getUpdates failed, retrying in {:?}: {}", wait, err);
+
+                    tokio::select! {
+                        _ = cancel.cancelled() => break,
+                        _ = tokio::time::sleep(wait) => continue,
+                    }
+                }
+            };
+            backoff = self.min_backoff;
+
+            for value in updates {
+                // Advance past updates that fail to parse too, or they come back every poll
+                if let Some(update_id) = value.get("update_id").and_then(|id| id.as_i64()) {
+                    offset = Some(update_id + 1);
+                }
+                let Some(update) = parse_update(value) else {
+                    continue;
+                };
+
+                let dispatcher = self.dispatcher.clone();
+                handlers.spawn(async move {
+                    dispatcher.dispatch(update).await;
+                });
+            }
+        }
+
+        // Confirm what we've already taken, otherwise it is redelivered on restart
+        if offset.is_some()
+            && let Err(err) = bot.get_raw_updates(offset, 0, 1, &allowed_updates).await
+        {
+            eprintln!("Failed to confirm Telegram updates: {}", err);
+        }
+
+        while handlers.join_next().await.is_some() {}
+    }
+}
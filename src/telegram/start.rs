@@ -0,0 +1,142 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+
+use super::dispatch::Command;
+
+// Telegram accepts up to 64 characters from A-Z, a-z, 0-9, _ and - in start / startapp
+pub const MAX_START_PARAM_LENGTH: usize = 64;
+
+const REFERRER_KEY: &str = "r";
+const CAMPAIGN_KEY: &str = "c";
+
+// Payload of `t.me/bot?start=` and `t.me/bot/app?startapp=` links, encoded as
+// URL-safe base64 of "r=<referrer>&c=<campaign>&<key>=<value>..."
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StartParam {
+    pub referrer: Option<i64>,
+    pub campaign: Option<String>,
+    pub params: Vec<(String, String)>,
+}
+
+impl StartParam {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn referrer(mut self, user_id: i64) -> Self {
+        self.referrer = Some(user_id);
+        self
+    }
+
+    pub fn campaign(mut self, campaign: impl Into<String>) -> Self {
+        self.campaign = Some(campaign.into());
+        self
+    }
+
+    pub fn param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.params.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.referrer.is_none() && self.campaign.is_none() && self.params.is_empty()
+    }
+
+    // Fails when the encoded value doesn't fit in 64 characters
+    pub fn encode(&self) -> Result<String, String> {
+        let mut pairs = Vec::new();
+        if let Some(referrer) = self.referrer {
+            pairs.push(format!("{}={}", REFERRER_KEY, referrer));
+        }
+        if let Some(campaign) = &self.campaign {
+            pairs.push(format!("{}={}", CAMPAIGN_KEY, urlencoding::encode(campaign)));
+        }
+        for (key, value) in &self.params {
+            pairs.push(format!("{}={}", urlencoding::encode(key), urlencoding::encode(value)));
+        }
+
+        let encoded = URL_SAFE_NO_PAD.encode(pairs.join("&"));
+        if encoded.len() > MAX_START_PARAM_LENGTH {
+            return Err(format!(
+                "Start parameter is {} characters, Telegram allows {}",
+                encoded.len(),
+                MAX_START_PARAM_LENGTH
+            ));
+        }
+
+        Ok(encoded)
+    }
+
+    // Also accepts the old "r=<id>q=<query>" payloads without a separator
+    pub fn decode(value: &str) -> Option<Self> {
+        let value = value.trim().trim_end_matches('=');
+        if value.is_empty() {
+            return Some(Self::default());
+        }
+
+        let bytes = URL_SAFE_NO_PAD.decode(value).ok()?;
+        let decoded = String::from_utf8(bytes).ok()?;
+
+        let mut param = Self::default();
+        for pair in decoded.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=')?;
+            let value = urlencoding::decode(value).ok()?.into_owned();
+
+            match key {
+                REFERRER_KEY => {
+                    let (referrer, query) = match value.split_once("q=") {
+                        Some((referrer, query)) => (referrer.to_string(), Some(query.to_string())),
+                        None => (value, None),
+                    };
+                    // A malformed referrer is dropped, the campaign and query still count
+                    param.referrer = referrer.parse().ok();
+                    if let Some(query) = query {
+                        param.params.push(("q".to_string(), query));
+                    }
+                }
+                CAMPAIGN_KEY => param.campaign = Some(value),
+                key => {
+                    let key = urlencoding::decode(key).ok()?.into_owned();
+                    param.params.push((key, value));
+                }
+            }
+        }
+
+        Some(param)
+    }
+
+    // From a "/start <payload>" command
+    pub fn from_command(command: &Command) -> Option<Self> {
+        if command.name != "start" {
+            return None;
+        }
+        Self::decode(command.args.split_whitespace().next().unwrap_or_default())
+    }
+
+    // From the start_param field of already validated initData
+    pub fn from_init_data(init_data: &str) -> Option<Self> {
+        Self::decode(&super::extract_start_param(init_data)?)
+    }
+
+    // t.me link opening a chat with the bot, delivered as "/start <payload>"
+    pub fn start_link(&self, bot_username: &str) -> Result<String, String> {
+        Ok(format!("https://t.me/{}?start={}", bot_username.trim_start_matches('@'), self.encode()?))
+    }
+
+    // t.me link opening a Mini App, or the bot's main Mini App without `app_name`
+    pub fn startapp_link(&self, bot_username: &str, app_name: Option<&str>) -> Result<String, String> {
+        let bot_username = bot_username.trim_start_matches('@');
+        let encoded = self.encode()?;
+
+        Ok(match app_name {
+            Some(app_name) => format!("https://t.me/{}/{}?startapp={}", bot_username, app_name, encoded),
+            None => format!("https://t.me/{}?startapp={}", bot_username, encoded),
+        })
+    }
+}
@@ -0,0 +1,236 @@
+use serde::{Deserialize, Serialize};
+
+// Bot API envelope: {"ok": true, "result": ...} or {"ok": false, "description": ..., "error_code": ...}
+#[derive(Debug, Deserialize)]
+pub struct TelegramResponse<T> {
+    pub ok: bool,
+    pub result: Option<T>,
+    pub description: Option<String>,
+    pub error_code: Option<i32>,
+    pub parameters: Option<ResponseParameters>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResponseParameters {
+    pub migrate_to_chat_id: Option<i64>,
+    pub retry_after: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ChatId {
+    Id(i64),
+    Username(String), // "@channelusername"
+}
+
+impl From<i64> for ChatId {
+    fn from(id: i64) -> Self {
+        ChatId::Id(id)
+    }
+}
+
+impl From<&str> for ChatId {
+    fn from(value: &str) -> Self {
+        value.to_string().into()
+    }
+}
+
+impl From<String> for ChatId {
+    fn from(value: String) -> Self {
+        // Numeric strings (e.g. "-100123...") are ids, the rest are usernames
+        match value.parse::<i64>() {
+            Ok(id) => ChatId::Id(id),
+            Err(_) => ChatId::Username(value),
+        }
+    }
+}
+
+impl std::fmt::Display for ChatId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChatId::Id(id) => write!(f, "{}", id),
+            ChatId::Username(username) => write!(f, "{}", username),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParseMode {
+    #[serde(rename = "HTML")]
+    Html,
+    MarkdownV2,
+    Markdown,
+}
+
+// Bot API user, unlike `telegram::User` (the Mini App user) it knows whether it is a bot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub id: i64,
+    #[serde(default)]
+    pub is_bot: bool,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub username: Option<String>,
+    pub language_code: Option<String>,
+    #[serde(default)]
+    pub is_premium: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chat {
+    pub id: i64,
+    #[serde(rename = "type")]
+    pub kind: String, // "private", "group", "supergroup" or "channel"
+    pub title: Option<String>,
+    pub username: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageEntity {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub offset: i64,
+    pub length: i64,
+    pub url: Option<String>,
+    pub user: Option<User>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub message_id: i64,
+    pub message_thread_id: Option<i64>,
+    pub from: Option<User>,
+    pub chat: Chat,
+    pub date: i64,
+    pub text: Option<String>,
+    pub entities: Option<Vec<MessageEntity>>,
+    pub caption: Option<String>,
+    pub photo: Option<Vec<PhotoSize>>,
+    pub document: Option<Document>,
+    pub successful_payment: Option<SuccessfulPayment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhotoSize {
+    pub file_id: String,
+    pub file_unique_id: String,
+    pub width: i64,
+    pub height: i64,
+    pub file_size: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document {
+    pub file_id: String,
+    pub file_unique_id: String,
+    pub file_name: Option<String>,
+    pub mime_type: Option<String>,
+    pub file_size: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct File {
+    pub file_id: String,
+    pub file_unique_id: String,
+    pub file_size: Option<i64>,
+    pub file_path: Option<String>, // valid for at least one hour
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserProfilePhotos {
+    pub total_count: i64,
+    pub photos: Vec<Vec<PhotoSize>>, // each photo in up to 4 sizes, smallest first
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatMemberStatus {
+    Creator,
+    Administrator,
+    Member,
+    Restricted,
+    Left,
+    Kicked,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMember {
+    pub status: ChatMemberStatus,
+    pub user: User,
+    pub is_member: Option<bool>, // only for "restricted"
+    pub until_date: Option<i64>,
+}
+
+// editMessageText returns the message, or `true` for inline messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Edited {
+    Message(Box<Message>),
+    Inline(bool),
+}
+
+// Incoming update, exactly one of the optional fields is set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Update {
+    pub update_id: i64,
+    pub message: Option<Message>,
+    pub edited_message: Option<Message>,
+    pub channel_post: Option<Message>,
+    pub callback_query: Option<CallbackQuery>,
+    pub inline_query: Option<InlineQuery>,
+    pub pre_checkout_query: Option<PreCheckoutQuery>,
+    pub my_chat_member: Option<ChatMemberUpdated>,
+    pub chat_member: Option<ChatMemberUpdated>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallbackQuery {
+    pub id: String,
+    pub from: User,
+    pub message: Option<Box<Message>>, // missing for inline messages, date == 0 if inaccessible
+    pub inline_message_id: Option<String>,
+    pub chat_instance: Option<String>,
+    pub data: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InlineQuery {
+    pub id: String,
+    pub from: User,
+    pub query: String,
+    pub offset: String,
+    pub chat_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreCheckoutQuery {
+    pub id: String,
+    pub from: User,
+    pub currency: String, // "XTR" for Telegram Stars
+    pub total_amount: i64,
+    pub invoice_payload: String,
+    pub shipping_option_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuccessfulPayment {
+    pub currency: String,
+    pub total_amount: i64,
+    pub invoice_payload: String,
+    pub telegram_payment_charge_id: String,
+    pub provider_payment_charge_id: Option<String>,
+    pub subscription_expiration_date: Option<i64>,
+    #[serde(default)]
+    pub is_recurring: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMemberUpdated {
+    pub chat: Chat,
+    pub from: User,
+    pub date: i64,
+    pub old_chat_member: ChatMember,
+    pub new_chat_member: ChatMember,
+}
@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+};
+
+use super::{dispatch::Dispatcher, types::Update};
+
+pub const SECRET_TOKEN_HEADER: &str = "x-telegram-bot-api-secret-token";
+
+struct Webhook {
+    dispatcher: Dispatcher,
+    secret_token: Option<String>,
+}
+
+// Router receiving webhook updates at `path`, checking the secret token passed to setWebhook
+pub fn router(dispatcher: Dispatcher, path: &str, secret_token: Option<String>) -> Router {
+    let state = Arc::new(Webhook { dispatcher, secret_token });
+
+    Router::new()
+        .route(path, post(receive))
+        .with_state(state)
+}
+
+async fn receive(
+    State(webhook): State<Arc<Webhook>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    if let Some(expected) = &webhook.secret_token {
+        let provided = headers
+            .get(SECRET_TOKEN_HEADER)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+
+        if !constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+            return StatusCode::UNAUTHORIZED;
+        }
+    }
+
+    // Telegram retries non-2xx responses, so an update we can't parse is
+    // logged and acknowledged instead of being redelivered forever
+    let update = match serde_json::from_slice::<Update>(&body) {
+        Ok(update) => update,
+        Err(err) => {
+            eprintln!("Invalid Telegram update: {:?}", err);
+            return StatusCode::OK;
+        }
+    };
+
+    // Answer right away, handlers may take longer than Telegram waits
+    let webhook = webhook.clone();
+    tokio::spawn(async move {
+        webhook.dispatcher.dispatch(update).await;
+    });
+
+    StatusCode::OK
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}